@@ -0,0 +1,34 @@
+#![no_main]
+
+use std::path::{Path, PathBuf};
+
+use code_generation::{
+    aspects, conflicts, diagnostic, generate, normalized, table, ControlStation,
+};
+use libfuzzer_sys::fuzz_target;
+
+// Run the generator on arbitrary input. Every problem must be reported as an error, none may
+// panic.
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Read the input like the generator does, with the checks that come before generating
+    let Ok(mut interlocking) = diagnostic::parse_input(Path::new("fuzz.json"), input) else {
+        return;
+    };
+    let _ = normalized::normalize(&interlocking);
+    if aspects::apply_speed_limits(&mut interlocking).is_err() {
+        return;
+    }
+    let Ok(derived_signals) = aspects::derive_aspects(&mut interlocking) else {
+        return;
+    };
+    let _ = conflicts::dropped_conflicts(&interlocking);
+    let _ = table::interlocking_table(&interlocking);
+    let control_station = ControlStation::Cli {
+        output: PathBuf::from("fuzz"),
+    };
+    let _ = generate::generate(&interlocking, &derived_signals, control_station);
+    let _ = generate::generate_tests(&interlocking);
+});
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use track_element::signal::MainSignalState::{self as Aspect, *};
+
+use crate::{
+    driveway::{
+        AdditionalSignalZs3Symbol, InterlockingRepr, MainSignalState, Realize, SignalState,
+        SupportedSignalStates, TrackElement,
+    },
+    generate::{GenerationError, Location},
+};
+
+/// A signal whose aspect depends on the aspect of the next main signal while a driveway
+/// over it is set
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedSignal {
+    pub start_signal: String,
+    pub end_signal: String,
+    pub signal: String,
+    /// The aspect while the next main signal shows proceed
+    pub proceed: MainSignalState,
+    /// The aspect while the next main signal shows stop
+    pub caution: MainSignalState,
+}
+
+impl Realize for DerivedSignal {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let DerivedSignal {
+            start_signal,
+            end_signal,
+            signal,
+            ..
+        } = self;
+        let proceed = self.proceed.realize()?;
+        let caution = self.caution.realize()?;
+        Ok(quote! {
+            grpc_control_station::DerivedAspect::new(#start_signal, #end_signal, #signal, #proceed, #caution)
+        })
+    }
+}
+
+// The gRPC control station decides which aspects show stop in the same way
+#[path = "../../grpc_control_station/src/stop_aspect.rs"]
+mod stop_aspect;
+
+/// Whether a signal state tells trains to stop, or at least not to pass on a driveway
+pub fn shows_stop(state: &SignalState) -> Result<bool, GenerationError> {
+    let main: Aspect = (&state.main).try_into()?;
+    Ok(stop_aspect::is_stop(&main))
+}
+
+/// Whether a signal state shows stop or announces that the next main signal shows stop. Such
+/// a signal does not announce a speed with Zs3v.
+pub fn announces_stop(state: &SignalState) -> Result<bool, GenerationError> {
+    let main: Aspect = (&state.main).try_into()?;
+    Ok(shows_stop(state)? || matches!(main, Ks2 | Ks2WithAdditionalLight | Vr0))
+}
+
+fn supports_all(
+    supported_states: &SupportedSignalStates,
+    aspects: &[Aspect],
+) -> Result<bool, GenerationError> {
+    let supported = supported_states
+        .main
+        .iter()
+        .map(TryInto::try_into)
+        .collect::<Result<Vec<Aspect>, _>>()?;
+    Ok(aspects.iter().all(|aspect| supported.contains(aspect)))
+}
+
+/// Derive the aspects of the start signal and of the distant signals of every driveway from
+/// the state of its end signal. A start signal that supports Ks1 and Ks2 shows Ks1 if the end
+/// signal shows proceed and Ks2 otherwise. Other signals of the driveway that support Vr1 and
+/// Vr0 announce the end signal in the same way. While the end signal shows proceed, both show
+/// the Zs3 of the end signal as Zs3v. If the driveway does not list one, they announce the Zs3
+/// of the driveways from the end signal, so the speed limits have to be applied first. Ks2 and
+/// Vr0 never show Zs3v.
+///
+/// The end signal shows stop unless the driveway lists a proceed aspect for it. The derived
+/// signals are returned so the control station can re-evaluate them when the driveways ahead
+/// are set or released. Only the gRPC control station can do that, so generating any other
+/// control station fails with [`GenerationError::DerivedAspectsNeedGrpc`].
+pub fn derive_aspects(
+    interlocking: &mut InterlockingRepr,
+) -> Result<Vec<DerivedSignal>, GenerationError> {
+    if !interlocking.derive_aspects {
+        return Ok(vec![]);
+    }
+
+    // The Zs3 that the start signal of the driveways from each signal shows, the lowest if
+    // they differ
+    let mut shown_zs3: HashMap<String, AdditionalSignalZs3Symbol> = HashMap::new();
+    for driveway in &interlocking.driveways {
+        for element in &driveway.states {
+            let TrackElement::Signal {
+                uuid,
+                state: SignalState { zs3: Some(zs3), .. },
+                ..
+            } = element
+            else {
+                continue;
+            };
+            if uuid != driveway.start_signal.id() {
+                continue;
+            }
+            match shown_zs3.get(uuid) {
+                Some(lower) if lower.speed() <= zs3.speed() => {}
+                _ => {
+                    shown_zs3.insert(uuid.clone(), zs3.clone());
+                }
+            }
+        }
+    }
+
+    let mut derived_signals = vec![];
+    for driveway in &mut interlocking.driveways {
+        let start_signal = driveway.start_signal.id().to_string();
+        let end_signal = driveway.end_signal.id().to_string();
+
+        let next_state = driveway.states.iter().find_map(|element| match element {
+            TrackElement::Signal { uuid, state, .. } if *uuid == end_signal => Some(state),
+            _ => None,
+        });
+        let (proceed, zs3v) = match next_state {
+            Some(state) => (
+                !shows_stop(state)?,
+                state
+                    .zs3
+                    .clone()
+                    .or_else(|| shown_zs3.get(&end_signal).cloned()),
+            ),
+            None => (false, None),
+        };
+
+        for element in &mut driveway.states {
+            let TrackElement::Signal {
+                uuid,
+                supported_states,
+                state,
+                ..
+            } = element
+            else {
+                continue;
+            };
+            let (proceed_aspect, caution_aspect) = if *uuid == start_signal {
+                (("ks1", Ks1), ("ks2", Ks2))
+            } else if *uuid == end_signal {
+                continue;
+            } else {
+                (("vr1", Vr1), ("vr0", Vr0))
+            };
+            if !supports_all(supported_states, &[proceed_aspect.1, caution_aspect.1])? {
+                continue;
+            }
+
+            let proceed_aspect = MainSignalState(proceed_aspect.0.to_string());
+            let caution_aspect = MainSignalState(caution_aspect.0.to_string());
+            if proceed {
+                state.main = proceed_aspect.clone();
+                state.zs3v = zs3v.clone();
+            } else {
+                state.main = caution_aspect.clone();
+                state.zs3v = None;
+            }
+
+            derived_signals.push(DerivedSignal {
+                start_signal: start_signal.clone(),
+                end_signal: end_signal.clone(),
+                signal: uuid.clone(),
+                proceed: proceed_aspect,
+                caution: caution_aspect,
+            });
+        }
+    }
+    Ok(derived_signals)
+}
+
+/// Show the maximum speed of every driveway that has one. Its start signal shows the Zs3 for
+/// the highest speed up to the maximum speed, and the start signals of the driveways leading
+/// to it announce that speed with Zs3v, unless they show stop or announce stop. If driveways
+/// with different maximum speeds start at the same signal, the lowest speed is announced.
+pub fn apply_speed_limits(interlocking: &mut InterlockingRepr) -> Result<(), GenerationError> {
+    // The Zs3 to announce in front of each signal, with the driveway and speed it comes from
+    let mut announced: HashMap<String, (AdditionalSignalZs3Symbol, String, u16, Location)> =
+        HashMap::new();
+
+    for (i, driveway) in interlocking.driveways.iter_mut().enumerate() {
+        let Some(speed) = driveway.max_speed else {
+            continue;
+        };
+        let name = driveway.name();
+        let location = Location::new(i, "/max_speed");
+        let symbol = AdditionalSignalZs3Symbol::for_speed(speed).ok_or_else(|| {
+            GenerationError::SpeedTooLow {
+                driveway: name.clone(),
+                speed,
+                location: location.clone(),
+            }
+        })?;
+        let TrackElement::Signal {
+            uuid: start_signal,
+            supported_states,
+            ..
+        } = &driveway.start_signal
+        else {
+            continue;
+        };
+        if !supported_states.supports_zs3(&symbol) {
+            return Err(GenerationError::UnsupportedSpeed {
+                driveway: name,
+                signal: start_signal.clone(),
+                indicator: "Zs3",
+                symbol: symbol.to_string(),
+                speed,
+                location,
+            });
+        }
+
+        let mut start_states = driveway
+            .states
+            .iter_mut()
+            .filter_map(|element| match element {
+                TrackElement::Signal { uuid, state, .. } if uuid == start_signal => Some(state),
+                _ => None,
+            })
+            .peekable();
+        if start_states.peek().is_none() {
+            return Err(GenerationError::NoStartSignalState {
+                driveway: name,
+                signal: start_signal.clone(),
+                location: Location::new(i, "/states"),
+            });
+        }
+        for state in start_states {
+            state.zs3 = Some(symbol.clone());
+        }
+
+        match announced.get(start_signal) {
+            Some((lower, ..)) if lower.speed() <= symbol.speed() => {}
+            _ => {
+                announced.insert(start_signal.clone(), (symbol, name, speed, location));
+            }
+        }
+    }
+
+    for driveway in &mut interlocking.driveways {
+        let Some((symbol, name, speed, location)) = announced.get(driveway.end_signal.id()) else {
+            continue;
+        };
+        let TrackElement::Signal {
+            uuid: start_signal,
+            supported_states,
+            ..
+        } = &driveway.start_signal
+        else {
+            continue;
+        };
+        let start_state = driveway.states.iter().find_map(|element| match element {
+            TrackElement::Signal { uuid, state, .. } if uuid == start_signal => Some(state),
+            _ => None,
+        });
+        if let Some(state) = start_state {
+            if announces_stop(state)? {
+                continue;
+            }
+        }
+        if !supported_states.supports_zs3v(symbol) {
+            return Err(GenerationError::UnsupportedSpeed {
+                driveway: name.clone(),
+                signal: start_signal.clone(),
+                indicator: "Zs3v",
+                symbol: symbol.to_string(),
+                speed: *speed,
+                location: location.clone(),
+            });
+        }
+
+        for element in &mut driveway.states {
+            match element {
+                TrackElement::Signal { uuid, state, .. } if uuid == start_signal => {
+                    state.zs3v = Some(symbol.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn signal(id: &str, main: &str) -> Value {
+        json!({
+            "type": "signal",
+            "uuid": id,
+            "supported_states": {"main": ["hp0", "ks1", "ks2"], "zs3": [6], "zs3v": [6]},
+            "state": {"main": main},
+        })
+    }
+
+    /// A→C, which ends at C with the given aspect, and C→D with a maximum speed of 60 km/h
+    fn interlocking(derive_aspects: bool, start: &str, end: &str) -> InterlockingRepr {
+        serde_json::from_value(json!({
+            "derive_aspects": derive_aspects,
+            "driveways": [
+                {
+                    "start_signal": signal("A", "hp0"),
+                    "end_signal": signal("C", "hp0"),
+                    "states": [signal("A", start), signal("C", end)],
+                },
+                {
+                    "start_signal": signal("C", "hp0"),
+                    "end_signal": signal("D", "hp0"),
+                    "states": [signal("C", "ks1"), signal("D", "hp0")],
+                    "max_speed": 60,
+                },
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn state<'a>(
+        interlocking: &'a InterlockingRepr,
+        driveway: usize,
+        signal: &str,
+    ) -> &'a SignalState {
+        interlocking.driveways[driveway]
+            .states
+            .iter()
+            .find_map(|element| match element {
+                TrackElement::Signal { uuid, state, .. } if uuid == signal => Some(state),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn speed_limits_show_zs3_and_zs3v() {
+        let mut interlocking = interlocking(false, "ks1", "ks1");
+        apply_speed_limits(&mut interlocking).unwrap();
+        assert_eq!(
+            state(&interlocking, 1, "C").zs3,
+            AdditionalSignalZs3Symbol::for_speed(60)
+        );
+        assert_eq!(
+            state(&interlocking, 0, "A").zs3v,
+            AdditionalSignalZs3Symbol::for_speed(60)
+        );
+    }
+
+    #[test]
+    fn speed_limits_need_a_state_for_the_start_signal() {
+        let mut interlocking = interlocking(false, "ks1", "ks1");
+        interlocking.driveways[1].states.remove(0);
+        assert!(matches!(
+            apply_speed_limits(&mut interlocking),
+            Err(GenerationError::NoStartSignalState { signal, .. }) if signal == "C"
+        ));
+    }
+
+    #[test]
+    fn caution_does_not_announce_a_speed() {
+        let mut interlocking = interlocking(false, "ks2", "hp0");
+        apply_speed_limits(&mut interlocking).unwrap();
+        assert_eq!(state(&interlocking, 0, "A").zs3v, None);
+    }
+
+    #[test]
+    fn derived_proceed_announces_the_speed_of_the_next_driveway() {
+        let mut interlocking = interlocking(true, "hp0", "ks1");
+        apply_speed_limits(&mut interlocking).unwrap();
+        let derived = derive_aspects(&mut interlocking).unwrap();
+        let start = state(&interlocking, 0, "A");
+        assert_eq!(start.main, MainSignalState("ks1".into()));
+        assert_eq!(start.zs3v, AdditionalSignalZs3Symbol::for_speed(60));
+        assert_eq!(derived.len(), 2);
+    }
+
+    #[test]
+    fn derived_caution_has_no_zs3v() {
+        let mut interlocking = interlocking(true, "ks1", "hp0");
+        apply_speed_limits(&mut interlocking).unwrap();
+        derive_aspects(&mut interlocking).unwrap();
+        let start = state(&interlocking, 0, "A");
+        assert_eq!(start.main, MainSignalState("ks2".into()));
+        assert_eq!(start.zs3v, None);
+    }
+}
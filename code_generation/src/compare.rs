@@ -0,0 +1,396 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use thiserror::Error;
+
+use crate::table::TableRow;
+
+#[derive(Debug, Error)]
+pub enum ReferenceTableError {
+    #[error("The reference table has no column `{0}`.")]
+    MissingColumn(&'static str),
+    #[error("The reference table contains an unterminated quoted field.")]
+    UnterminatedQuote,
+    #[error("Row {0} of the reference table has too few columns.")]
+    ShortRow(usize),
+}
+
+/// A difference between the reference table and the computed one
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    /// The route is in the reference table, but not in the input
+    MissingRoute(String),
+    /// The route is in the input, but not in the reference table
+    ExtraRoute(String),
+    PointPosition {
+        route: String,
+        point: String,
+        reference: Option<String>,
+        computed: Option<String>,
+    },
+    /// The reference table lists a conflict that was not computed
+    MissingConflict { route: String, conflict: String },
+    /// A conflict was computed that the reference table does not list
+    ExtraConflict { route: String, conflict: String },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::MissingRoute(route) => write!(f, "Missing route {route}"),
+            Difference::ExtraRoute(route) => write!(f, "Extra route {route}"),
+            Difference::PointPosition {
+                route,
+                point,
+                reference,
+                computed,
+            } => write!(
+                f,
+                "Route {route}: point {point} is {} in the reference table, but {} in the input",
+                reference.as_deref().unwrap_or("not needed"),
+                computed.as_deref().unwrap_or("not needed")
+            ),
+            Difference::MissingConflict { route, conflict } => {
+                write!(f, "Route {route}: missing conflict with {conflict}")
+            }
+            Difference::ExtraConflict { route, conflict } => {
+                write!(f, "Route {route}: extra conflict with {conflict}")
+            }
+        }
+    }
+}
+
+/// A route of the interlocking table, reduced to the parts that are compared
+struct Route {
+    points: BTreeMap<String, String>,
+    conflicts: BTreeSet<String>,
+}
+
+impl Route {
+    fn new(points: &str, conflicts: &str) -> Result<Self, ReferenceTableError> {
+        let mut point_positions = BTreeMap::new();
+        for entry in split_unquoted(points, ',')? {
+            if let [point, position] = split_unquoted(entry, ':')?[..] {
+                point_positions.insert(unquote(point), unquote(position).to_lowercase());
+            }
+        }
+        let conflicts = split_unquoted(conflicts, ',')?
+            .into_iter()
+            .map(unquote)
+            .collect();
+        Ok(Route {
+            points: point_positions,
+            conflicts,
+        })
+    }
+}
+
+/// Split a list in a cell at the separators outside of quotes. Like the table itself, lists
+/// quote their entries if they contain a separator.
+fn split_unquoted(cell: &str, separator: char) -> Result<Vec<&str>, ReferenceTableError> {
+    let mut entries = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in cell.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            entries.push(cell[start..i].trim());
+            start = i + c.len_utf8();
+        }
+    }
+    if quoted {
+        return Err(ReferenceTableError::UnterminatedQuote);
+    }
+    entries.push(cell[start..].trim());
+    Ok(entries
+        .into_iter()
+        .filter(|entry| !entry.is_empty())
+        .collect())
+}
+
+/// Remove the quotes around a list entry
+fn unquote(entry: &str) -> String {
+    match entry
+        .strip_prefix('"')
+        .and_then(|entry| entry.strip_suffix('"'))
+    {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => entry.to_string(),
+    }
+}
+
+fn route_name(start_signal: &str, end_signal: &str) -> String {
+    format!("{start_signal} - {end_signal}")
+}
+
+/// Split CSV text into rows of fields. Fields may be quoted, quotes in quoted fields are
+/// escaped by doubling them.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>, ReferenceTableError> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            ('\r', false) => {}
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(ReferenceTableError::UnterminatedQuote);
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Read the routes of a reference table in the CSV format written by the `table` subcommand.
+/// Columns are found by their header, so the reference table may contain additional columns.
+fn parse_reference_table(csv: &str) -> Result<BTreeMap<String, Route>, ReferenceTableError> {
+    let mut rows = parse_csv(csv)?.into_iter();
+    let header = rows.next().unwrap_or_default();
+    let column = |name: &'static str| {
+        header
+            .iter()
+            .position(|cell| cell.trim() == name)
+            .ok_or(ReferenceTableError::MissingColumn(name))
+    };
+    let start_signal = column("Start signal")?;
+    let end_signal = column("End signal")?;
+    let points = column("Points")?;
+    let conflicts = column("Conflicts")?;
+    let width = start_signal.max(end_signal).max(points).max(conflicts) + 1;
+
+    let mut routes = BTreeMap::new();
+    for (i, row) in rows.enumerate() {
+        if row.iter().all(|cell| cell.trim().is_empty()) {
+            continue;
+        }
+        if row.len() < width {
+            // Rows are counted from 1, after the header
+            return Err(ReferenceTableError::ShortRow(i + 2));
+        }
+        routes.insert(
+            route_name(row[start_signal].trim(), row[end_signal].trim()),
+            Route::new(&row[points], &row[conflicts])?,
+        );
+    }
+    Ok(routes)
+}
+
+/// Compare the computed interlocking table with a reference table in CSV format
+pub fn compare(
+    computed: &[TableRow],
+    reference_csv: &str,
+) -> Result<Vec<Difference>, ReferenceTableError> {
+    let reference = parse_reference_table(reference_csv)?;
+    let computed = computed
+        .iter()
+        .map(|row| {
+            Ok((
+                route_name(&row.start_signal, &row.end_signal),
+                Route::new(&row.points, &row.conflicts)?,
+            ))
+        })
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+
+    let mut differences = vec![];
+    for name in reference.keys() {
+        if !computed.contains_key(name) {
+            differences.push(Difference::MissingRoute(name.clone()));
+        }
+    }
+
+    for (name, route) in &computed {
+        let Some(reference_route) = reference.get(name) else {
+            differences.push(Difference::ExtraRoute(name.clone()));
+            continue;
+        };
+
+        let points: BTreeSet<_> = route
+            .points
+            .keys()
+            .chain(reference_route.points.keys())
+            .collect();
+        for point in points {
+            let reference_position = reference_route.points.get(point);
+            let computed_position = route.points.get(point);
+            if reference_position != computed_position {
+                differences.push(Difference::PointPosition {
+                    route: name.clone(),
+                    point: point.clone(),
+                    reference: reference_position.cloned(),
+                    computed: computed_position.cloned(),
+                });
+            }
+        }
+
+        for conflict in reference_route.conflicts.difference(&route.conflicts) {
+            differences.push(Difference::MissingConflict {
+                route: name.clone(),
+                conflict: conflict.clone(),
+            });
+        }
+        for conflict in route.conflicts.difference(&reference_route.conflicts) {
+            differences.push(Difference::ExtraConflict {
+                route: name.clone(),
+                conflict: conflict.clone(),
+            });
+        }
+    }
+
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        driveway::InterlockingRepr,
+        table::{interlocking_table, render, TableFormat},
+    };
+
+    fn row(start_signal: &str, end_signal: &str, points: &str, conflicts: &str) -> TableRow {
+        TableRow {
+            start_signal: start_signal.into(),
+            end_signal: end_signal.into(),
+            points: points.into(),
+            vacancy_sections: String::new(),
+            aspects: String::new(),
+            conflicts: conflicts.into(),
+        }
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        let rows = parse_csv("a,\"b, \"\"c\"\"\"\r\n\"d\ne\",\n").unwrap();
+        assert_eq!(rows, vec![vec!["a", "b, \"c\""], vec!["d\ne", ""]]);
+        assert!(matches!(
+            parse_csv("a,\"b"),
+            Err(ReferenceTableError::UnterminatedQuote)
+        ));
+    }
+
+    #[test]
+    fn reads_quoted_list_entries() {
+        let route = Route::new(r#""W,1": Left, "W:2": right"#, r#""A, 1 - C", B - D"#).unwrap();
+        assert_eq!(
+            route.points,
+            BTreeMap::from([
+                ("W,1".to_string(), "left".to_string()),
+                ("W:2".to_string(), "right".to_string())
+            ])
+        );
+        assert_eq!(
+            route.conflicts,
+            BTreeSet::from(["A, 1 - C".to_string(), "B - D".to_string()])
+        );
+    }
+
+    #[test]
+    fn finds_columns_by_header() {
+        let csv = "Conflicts,Remark,End signal,Points,Start signal\nX - Y,ok,C,W: left,A\n";
+        let routes = parse_reference_table(csv).unwrap();
+        let route = &routes["A - C"];
+        assert_eq!(route.points["W"], "left");
+        assert!(route.conflicts.contains("X - Y"));
+
+        assert!(matches!(
+            parse_reference_table("Start signal,End signal,Points\n"),
+            Err(ReferenceTableError::MissingColumn("Conflicts"))
+        ));
+        assert!(matches!(
+            parse_reference_table("Start signal,End signal,Points,Conflicts\nA,C\n"),
+            Err(ReferenceTableError::ShortRow(2))
+        ));
+    }
+
+    #[test]
+    fn reports_differences() {
+        let computed = [
+            row("A", "C", "W1: left", "D - F"),
+            row("D", "F", "W1: right", "A - C"),
+            row("G", "H", "", ""),
+        ];
+        let reference = "Start signal,End signal,Points,Conflicts\n\
+            A,C,W1: left,\n\
+            D,F,\"W1: left, W2: right\",A - C\n\
+            X,Y,,\n";
+        assert_eq!(
+            compare(&computed, reference).unwrap(),
+            vec![
+                Difference::MissingRoute("X - Y".into()),
+                Difference::ExtraConflict {
+                    route: "A - C".into(),
+                    conflict: "D - F".into()
+                },
+                Difference::PointPosition {
+                    route: "D - F".into(),
+                    point: "W1".into(),
+                    reference: Some("left".into()),
+                    computed: Some("right".into())
+                },
+                Difference::PointPosition {
+                    route: "D - F".into(),
+                    point: "W2".into(),
+                    reference: Some("right".into()),
+                    computed: None
+                },
+                Difference::ExtraRoute("G - H".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn exported_table_matches_itself() {
+        let signal = |id: &str| {
+            json!({
+                "type": "signal",
+                "uuid": id,
+                "supported_states": {"main": ["hp0", "ks1"]},
+                "state": {"main": "ks1"},
+            })
+        };
+        let interlocking: InterlockingRepr = serde_json::from_value(json!([
+            {
+                "start_signal": signal("A, 1"),
+                "end_signal": signal("C"),
+                "states": [{"type": "point", "uuid": "W:1", "state": "left"}],
+            },
+            {
+                "start_signal": signal("D"),
+                "end_signal": signal("F"),
+                "states": [{"type": "point", "uuid": "W:1", "state": "right"}],
+            },
+        ]))
+        .unwrap();
+        let rows = interlocking_table(&interlocking).unwrap();
+        let csv = render(&rows, TableFormat::Csv);
+        assert_eq!(compare(&rows, &csv).unwrap(), vec![]);
+
+        let reference = parse_reference_table(&csv).unwrap();
+        assert_eq!(reference["A, 1 - C"].points["W:1"], "left");
+        assert!(reference["A, 1 - C"].conflicts.contains("D - F"));
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    aspects::shows_stop,
+    driveway::{
+        ConflictMode, DrivewayRef, DrivewayRepr, InterlockingRepr, SignalState, TrackElement,
+    },
+    generate::GenerationError,
+};
+
+/// The reason why two driveways cannot be set at the same time
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+    /// Both driveways start at the same signal
+    SameStartSignal(String),
+    /// Both driveways lead to the same signal
+    ConvergingMovements(String),
+    /// The driveways run over the same track in opposite directions
+    OpposingMovements,
+    /// Both driveways need a point, but in different positions
+    PointPosition(String),
+    /// Both driveways run through the same vacancy section
+    SharedVacancySection(String),
+    /// Both driveways need a signal to show a proceed aspect, but different ones
+    SignalAspect(String),
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::SameStartSignal(signal) => write!(f, "both start at signal {signal}"),
+            Conflict::ConvergingMovements(signal) => write!(f, "both lead to signal {signal}"),
+            Conflict::OpposingMovements => write!(f, "opposing movements"),
+            Conflict::PointPosition(point) => {
+                write!(f, "point {point} is needed in different positions")
+            }
+            Conflict::SharedVacancySection(vacancy_section) => {
+                write!(f, "both run through vacancy section {vacancy_section}")
+            }
+            Conflict::SignalAspect(signal) => {
+                write!(f, "signal {signal} is needed with different aspects")
+            }
+        }
+    }
+}
+
+/// Whether two driveways pass the points and vacancy sections they share in opposite order.
+/// The `states` of a driveway list its track elements in the order the driveway passes them.
+fn runs_opposite(driveway: &DrivewayRepr, other: &DrivewayRepr) -> bool {
+    fn track_elements(driveway: &DrivewayRepr) -> Vec<&str> {
+        driveway
+            .states
+            .iter()
+            .filter(|element| !matches!(element, TrackElement::Signal { .. }))
+            .map(TrackElement::id)
+            .collect()
+    }
+    let other_track = track_elements(other);
+    let positions: Vec<_> = track_elements(driveway)
+        .iter()
+        .filter_map(|id| other_track.iter().position(|other_id| other_id == id))
+        .collect();
+    positions.len() > 1 && positions.windows(2).all(|pair| pair[0] > pair[1])
+}
+
+/// Whether a signal has to show stop. Unknown aspects are reported by the signal state checks.
+fn needs_stop(state: &SignalState) -> bool {
+    matches!(shows_stop(state), Ok(true))
+}
+
+/// Decide whether two driveways conflict. Driveways that only share signals with the same
+/// aspect, or signals that one of them needs at stop, can be set at the same time. The aspect
+/// that a driveway lists for its end signal does not count, since the driveway that starts
+/// there sets it.
+///
+/// Driveways only overlap if they share a vacancy section or lead to the same signal. A point
+/// that both need in the same position is no conflict on its own, e.g. a flank protection point.
+pub fn conflict(driveway: &DrivewayRepr, other: &DrivewayRepr) -> Option<Conflict> {
+    let start = driveway.start_signal.id();
+    let end = driveway.end_signal.id();
+    let other_start = other.start_signal.id();
+    let other_end = other.end_signal.id();
+
+    if start == other_start {
+        return Some(Conflict::SameStartSignal(start.to_string()));
+    }
+    if end == other_end {
+        return Some(Conflict::ConvergingMovements(end.to_string()));
+    }
+    if (start == other_end && end == other_start) || runs_opposite(driveway, other) {
+        return Some(Conflict::OpposingMovements);
+    }
+
+    for element in &driveway.states {
+        for other_element in other.states.iter().filter(|o| o.id() == element.id()) {
+            match (element, other_element) {
+                (
+                    TrackElement::Point { uuid, state },
+                    TrackElement::Point {
+                        state: other_state, ..
+                    },
+                ) if state != other_state => {
+                    return Some(Conflict::PointPosition(uuid.clone()));
+                }
+                (
+                    TrackElement::VacancySection { uuid, .. },
+                    TrackElement::VacancySection { .. },
+                ) => {
+                    return Some(Conflict::SharedVacancySection(uuid.clone()));
+                }
+                (
+                    TrackElement::Signal { uuid, state, .. },
+                    TrackElement::Signal {
+                        state: other_state, ..
+                    },
+                ) if state != other_state
+                    && !needs_stop(state)
+                    && !needs_stop(other_state)
+                    && uuid != end
+                    && uuid != other_end =>
+                {
+                    return Some(Conflict::SignalAspect(uuid.clone()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// The old, coarse conflict heuristic: two driveways conflict if they share a track element
+fn shares_track_element(driveway: &DrivewayRepr, other: &DrivewayRepr) -> bool {
+    driveway
+        .states
+        .iter()
+        .any(|element| other.states.iter().any(|o| element.id() == o.id()))
+}
+
+/// The explicit conflicts from the input, in both directions
+fn explicit_conflicts(
+    interlocking: &InterlockingRepr,
+) -> Result<HashSet<(String, String)>, GenerationError> {
+    let driveway_ids: HashSet<_> = interlocking
+        .driveways
+        .iter()
+        .map(DrivewayRepr::id)
+        .collect();
+    let known = |driveway: &DrivewayRef| {
+        let id = driveway.id();
+        if driveway_ids.contains(&id) {
+            Ok(id)
+        } else {
+            Err(GenerationError::UnknownDriveway(driveway.to_string()))
+        }
+    };
+
+    let mut explicit_conflicts = HashSet::new();
+    let mut add = |driveway: String, other: &DrivewayRef| -> Result<(), GenerationError> {
+        let other = known(other)?;
+        explicit_conflicts.insert((other.clone(), driveway.clone()));
+        explicit_conflicts.insert((driveway, other));
+        Ok(())
+    };
+    for driveway in &interlocking.driveways {
+        for other in driveway.conflicts.iter().flatten() {
+            add(driveway.id(), other)?;
+        }
+    }
+    for [driveway, other] in &interlocking.conflicts {
+        add(known(driveway)?, other)?;
+    }
+    Ok(explicit_conflicts)
+}
+
+/// The IDs of the driveways each driveway conflicts with. Explicit conflicts from the input
+/// are added to the computed ones or replace them, depending on the conflict mode.
+pub fn compute_conflicting_driveways(
+    interlocking: &InterlockingRepr,
+) -> Result<HashMap<String, Vec<String>>, GenerationError> {
+    let routes = &interlocking.driveways;
+    let explicit_conflicts = explicit_conflicts(interlocking)?;
+    let is_explicit = |driveway: &DrivewayRepr, other: &DrivewayRepr| {
+        explicit_conflicts.contains(&(driveway.id(), other.id()))
+    };
+
+    let mut conflicting_driveways = HashMap::new();
+    for driveway in routes {
+        let conflicts = routes
+            .iter()
+            .filter(|other| driveway != *other)
+            .filter(|other| match interlocking.conflict_mode {
+                ConflictMode::Merge => {
+                    is_explicit(driveway, other) || conflict(driveway, other).is_some()
+                }
+                ConflictMode::Replace => is_explicit(driveway, other),
+            })
+            .map(DrivewayRepr::id)
+            .collect();
+        conflicting_driveways.insert(driveway.id(), conflicts);
+    }
+
+    Ok(conflicting_driveways)
+}
+
+/// Pairs of driveways that share a track element, but are not conflicting because an explicit
+/// conflict table replaces the computed conflicts. These are most likely mistakes in the table.
+pub fn dropped_conflicts(
+    interlocking: &InterlockingRepr,
+) -> Result<Vec<(String, String)>, GenerationError> {
+    if interlocking.conflict_mode != ConflictMode::Replace {
+        return Ok(vec![]);
+    }
+
+    let routes = &interlocking.driveways;
+    let explicit_conflicts = explicit_conflicts(interlocking)?;
+    let mut dropped_conflicts = vec![];
+    for (i, driveway) in routes.iter().enumerate() {
+        for other in &routes[i + 1..] {
+            let pair = (driveway.id(), other.id());
+            if shares_track_element(driveway, other) && !explicit_conflicts.contains(&pair) {
+                dropped_conflicts.push((driveway.name(), other.name()));
+            }
+        }
+    }
+    Ok(dropped_conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn signal(id: &str, main: &str) -> Value {
+        json!({
+            "type": "signal",
+            "uuid": id,
+            "supported_states": {"main": ["hp0", "ks1", "ks2"]},
+            "state": {"main": main},
+        })
+    }
+
+    fn point(id: &str, state: &str) -> Value {
+        json!({"type": "point", "uuid": id, "state": state})
+    }
+
+    fn section(id: &str) -> Value {
+        json!({"type": "vacancy_section", "uuid": id, "state": "free", "previous_signals": []})
+    }
+
+    fn driveway(start: &str, end: &str, states: Vec<Value>) -> DrivewayRepr {
+        serde_json::from_value(json!({
+            "start_signal": signal(start, "ks1"),
+            "end_signal": signal(end, "hp0"),
+            "states": states,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn follow_on_driveways_do_not_conflict() {
+        let first = driveway("A", "C", vec![signal("A", "ks1"), signal("C", "hp0")]);
+        let second = driveway("C", "D", vec![signal("C", "ks1"), signal("D", "hp0")]);
+        assert_eq!(conflict(&first, &second), None);
+        assert_eq!(conflict(&second, &first), None);
+    }
+
+    #[test]
+    fn end_signals_are_set_by_the_next_driveway() {
+        let first = driveway("A", "C", vec![signal("A", "ks1"), signal("C", "ks1")]);
+        let second = driveway("C", "D", vec![signal("C", "ks2"), signal("D", "hp0")]);
+        assert_eq!(conflict(&first, &second), None);
+    }
+
+    #[test]
+    fn different_proceed_aspects_conflict() {
+        let first = driveway("A", "C", vec![signal("X", "ks1")]);
+        let second = driveway("D", "F", vec![signal("X", "ks2")]);
+        assert_eq!(
+            conflict(&first, &second),
+            Some(Conflict::SignalAspect("X".into()))
+        );
+    }
+
+    #[test]
+    fn same_start_and_end_signals_conflict() {
+        let first = driveway("A", "C", vec![]);
+        assert_eq!(
+            conflict(&first, &driveway("A", "D", vec![])),
+            Some(Conflict::SameStartSignal("A".into()))
+        );
+        assert_eq!(
+            conflict(&first, &driveway("B", "C", vec![])),
+            Some(Conflict::ConvergingMovements("C".into()))
+        );
+    }
+
+    #[test]
+    fn opposing_driveways_conflict() {
+        let first = driveway("A", "C", vec![]);
+        let second = driveway("C", "A", vec![]);
+        assert_eq!(conflict(&first, &second), Some(Conflict::OpposingMovements));
+
+        let first = driveway(
+            "A",
+            "C",
+            vec![section("1"), point("W", "left"), section("2")],
+        );
+        let second = driveway(
+            "D",
+            "B",
+            vec![section("2"), point("W", "left"), section("1")],
+        );
+        assert_eq!(conflict(&first, &second), Some(Conflict::OpposingMovements));
+    }
+
+    #[test]
+    fn different_point_positions_conflict() {
+        let first = driveway("A", "C", vec![point("W", "left")]);
+        let second = driveway("D", "F", vec![point("W", "right")]);
+        assert_eq!(
+            conflict(&first, &second),
+            Some(Conflict::PointPosition("W".into()))
+        );
+    }
+
+    #[test]
+    fn points_in_the_same_position_do_not_conflict() {
+        let first = driveway("D", "F", vec![point("E", "left")]);
+        let second = driveway("G", "H", vec![point("E", "left")]);
+        assert_eq!(conflict(&first, &second), None);
+
+        // Like A - C and C - D in the example, which both need point B left
+        let first = driveway("A", "C", vec![point("B", "left")]);
+        let second = driveway("C", "D", vec![point("B", "left")]);
+        assert_eq!(conflict(&first, &second), None);
+    }
+
+    #[test]
+    fn vacancy_sections_decide_about_overlaps() {
+        let first = driveway("D", "F", vec![point("E", "left"), section("1")]);
+        let flank = driveway("G", "H", vec![point("E", "left"), section("2")]);
+        assert_eq!(conflict(&first, &flank), None);
+
+        let overlapping = driveway("G", "H", vec![point("E", "left"), section("1")]);
+        assert_eq!(
+            conflict(&first, &overlapping),
+            Some(Conflict::SharedVacancySection("1".into()))
+        );
+    }
+
+    #[test]
+    fn explicit_conflicts_name_driveways_by_their_signals() {
+        let driveway_ref = |start: &str, end: &str| DrivewayRef {
+            start_signal: start.to_string(),
+            end_signal: end.to_string(),
+        };
+        let mut interlocking: InterlockingRepr = vec![
+            driveway("A-1", "C", vec![point("W", "left")]),
+            driveway("D", "F", vec![point("W", "left")]),
+        ]
+        .into();
+        interlocking.conflict_mode = ConflictMode::Replace;
+        assert_eq!(
+            dropped_conflicts(&interlocking).unwrap(),
+            vec![("A-1 - C".to_string(), "D - F".to_string())]
+        );
+
+        interlocking.conflicts = vec![[driveway_ref("A-1", "C"), driveway_ref("A-1", "X")]];
+        let error = dropped_conflicts(&interlocking).unwrap_err();
+        assert!(
+            matches!(&error, GenerationError::UnknownDriveway(driveway) if driveway == "A-1 - X")
+        );
+    }
+}
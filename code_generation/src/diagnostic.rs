@@ -0,0 +1,538 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    driveway::{DrivewayRepr, InterlockingObject, InterlockingRepr},
+    generate::{GenerationError, Location},
+    normalized::NormalizedInput,
+    validate::{validate, ValidationError},
+};
+
+/// A problem with the input, with as much as is known about where it is
+#[derive(Debug, Default, PartialEq)]
+pub struct Diagnostic {
+    pub file: Option<PathBuf>,
+    /// Line and column, both starting at 1
+    pub position: Option<(usize, usize)>,
+    /// The JSON pointer of the value the problem refers to
+    pub pointer: Option<String>,
+    pub driveway: Option<String>,
+    pub element: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(file: Option<&Path>, message: String) -> Self {
+        Diagnostic {
+            file: file.map(Path::to_path_buf),
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+/// An input file and its text, which diagnostics point into
+#[derive(Clone, Copy, Debug)]
+pub struct Source<'a> {
+    pub file: &'a Path,
+    pub text: &'a str,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+            if let Some((line, column)) = self.position {
+                write!(f, "{line}:{column}:")?;
+            }
+            write!(f, " ")?;
+        }
+        if let Some(pointer) = &self.pointer {
+            write!(f, "{pointer}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+
+        match (&self.driveway, &self.element) {
+            (Some(driveway), Some(element)) => {
+                write!(f, " (driveway {driveway}, element {element})")
+            }
+            (Some(driveway), None) => write!(f, " (driveway {driveway})"),
+            (None, Some(element)) => write!(f, " (element {element})"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A diagnostic for an error of `serde_json`, which knows the line and column
+fn json_diagnostic(file: &Path, error: &serde_json::Error, pointer: Option<String>) -> Diagnostic {
+    let mut message = error.to_string();
+    let mut position = None;
+    if error.line() > 0 {
+        // Display appends the position, which the diagnostic shows on its own
+        let suffix = format!(" at line {} column {}", error.line(), error.column());
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+        position = Some((error.line(), error.column()));
+    }
+    Diagnostic {
+        file: Some(file.to_path_buf()),
+        position,
+        pointer,
+        message,
+        ..Default::default()
+    }
+}
+
+/// Undo the escaping of a JSON pointer token
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// The ID of a track element, given in full or as a reference
+fn element_id(element: &Value) -> Option<String> {
+    match element {
+        Value::String(id) => Some(id.clone()),
+        Value::Object(object) => match object.get("uuid").or_else(|| object.get("element")) {
+            Some(Value::String(id)) => Some(id.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The driveway and track element that a JSON pointer into the input points into
+fn context(input: &Value, pointer: &str) -> (Option<String>, Option<String>) {
+    let tokens: Vec<_> = pointer.split('/').skip(1).map(unescape_token).collect();
+    let tokens: Vec<_> = tokens.iter().map(String::as_str).collect();
+
+    let (driveway_pointer, rest) = match tokens.as_slice() {
+        ["driveways", i, rest @ ..] => (format!("/driveways/{i}"), rest),
+        [i, rest @ ..] if i.parse::<usize>().is_ok() => (format!("/{i}"), rest),
+        ["elements", i, ..] => {
+            let element = input
+                .pointer(&format!("/elements/{i}"))
+                .and_then(element_id);
+            return (None, element);
+        }
+        ["initial_state", _, id, ..] => return (None, Some(id.to_string())),
+        _ => return (None, None),
+    };
+    let Some(driveway) = input.pointer(&driveway_pointer) else {
+        return (None, None);
+    };
+
+    let driveway_name = match (
+        driveway.get("start_signal").and_then(element_id),
+        driveway.get("end_signal").and_then(element_id),
+    ) {
+        (Some(start), Some(end)) => Some(format!("{start} - {end}")),
+        _ => None,
+    };
+    let element = match rest {
+        ["states", j, ..] => driveway
+            .get("states")
+            .and_then(|states| states.get(j.parse::<usize>().ok()?))
+            .and_then(element_id),
+        [signal @ ("start_signal" | "end_signal"), ..] => driveway.get(signal).and_then(element_id),
+        _ => None,
+    };
+    (driveway_name, element)
+}
+
+/// Finds values in the text of a JSON document. The text must be valid JSON.
+struct Scanner<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        let whitespace = self.text[self.offset..]
+            .bytes()
+            .take_while(u8::is_ascii_whitespace)
+            .count();
+        self.offset += whitespace;
+        self.text.as_bytes().get(self.offset).copied()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let start = self.offset;
+        let mut escaped = false;
+        for (i, byte) in self.text[start + 1..].bytes().enumerate() {
+            match byte {
+                b'\\' => escaped = !escaped,
+                b'"' if !escaped => {
+                    self.offset = start + i + 2;
+                    return serde_json::from_str(&self.text[start..self.offset]).ok();
+                }
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        let mut depth = 0;
+        loop {
+            match self.peek()? {
+                b'"' => {
+                    self.string()?;
+                }
+                b'{' | b'[' => {
+                    depth += 1;
+                    self.offset += 1;
+                }
+                b'}' | b']' => {
+                    depth -= 1;
+                    self.offset += 1;
+                }
+                b',' | b':' => self.offset += 1,
+                _ => {
+                    let literal = self.text[self.offset..]
+                        .bytes()
+                        .take_while(|byte| {
+                            !matches!(byte, b',' | b'}' | b']') && !byte.is_ascii_whitespace()
+                        })
+                        .count();
+                    self.offset += literal;
+                }
+            }
+            if depth == 0 {
+                return Some(());
+            }
+        }
+    }
+
+    /// Find the offset of the value at `tokens` below the value at the current offset
+    fn find(&mut self, tokens: &[String]) -> Option<usize> {
+        let Some((token, rest)) = tokens.split_first() else {
+            self.peek()?;
+            return Some(self.offset);
+        };
+        match self.peek()? {
+            b'{' => {
+                self.offset += 1;
+                while self.peek()? == b'"' {
+                    let key = self.string()?;
+                    if self.peek()? != b':' {
+                        return None;
+                    }
+                    self.offset += 1;
+                    if key == *token {
+                        return self.find(rest);
+                    }
+                    self.skip_value()?;
+                    if self.peek()? == b',' {
+                        self.offset += 1;
+                    }
+                }
+                None
+            }
+            b'[' => {
+                self.offset += 1;
+                for _ in 0..token.parse::<usize>().ok()? {
+                    if self.peek()? == b']' {
+                        return None;
+                    }
+                    self.skip_value()?;
+                    if self.peek()? != b',' {
+                        return None;
+                    }
+                    self.offset += 1;
+                }
+                if self.peek()? == b']' {
+                    return None;
+                }
+                self.find(rest)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The line and column of the value that a JSON pointer refers to in the text of a JSON
+/// document, both starting at 1
+fn pointer_position(text: &str, pointer: &str) -> Option<(usize, usize)> {
+    let tokens: Vec<_> = pointer.split('/').skip(1).map(unescape_token).collect();
+    let offset = Scanner { text, offset: 0 }.find(&tokens)?;
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    Some((line, column))
+}
+
+/// The JSON pointer of a location in the input. If the value does not exist, e.g. because a
+/// previous signal is described in the element catalog, it is the closest value that contains
+/// it.
+fn location_pointer(input: &Value, location: &Location) -> Option<String> {
+    let driveways = if input.is_array() { "" } else { "/driveways" };
+    let mut pointer = format!("{driveways}/{}{}", location.driveway, location.path);
+    while input.pointer(&pointer).is_none() {
+        pointer.truncate(pointer.rfind('/')?);
+    }
+    Some(pointer).filter(|pointer| !pointer.is_empty())
+}
+
+fn validation_diagnostic(file: &Path, input: &Value, error: ValidationError) -> Diagnostic {
+    let (driveway, element) = context(input, &error.path);
+    Diagnostic {
+        file: Some(file.to_path_buf()),
+        pointer: (!error.path.is_empty()).then_some(error.path),
+        driveway,
+        element,
+        message: error.message,
+        ..Default::default()
+    }
+}
+
+/// Deserialize one of the input formats. Errors point to the value that could not be read.
+fn deserialize<T: DeserializeOwned>(
+    file: &Path,
+    text: &str,
+    input: &Value,
+) -> Result<T, Vec<Diagnostic>> {
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+        let pointer: String = error
+            .path()
+            .iter()
+            .filter_map(|segment| match segment {
+                serde_path_to_error::Segment::Seq { index } => Some(format!("/{index}")),
+                serde_path_to_error::Segment::Map { key } => {
+                    Some(format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+                }
+                _ => None,
+            })
+            .collect();
+        let (driveway, element) = context(input, &pointer);
+        vec![Diagnostic {
+            driveway,
+            element,
+            ..json_diagnostic(file, error.inner(), Some(pointer).filter(|p| !p.is_empty()))
+        }]
+    })
+}
+
+/// Read the text of an input file
+pub fn read_text(file: &Path) -> Result<String, Vec<Diagnostic>> {
+    std::fs::read_to_string(file).map_err(|e| vec![Diagnostic::new(Some(file), e.to_string())])
+}
+
+/// Check and read the text of an input file. Every problem that is found is reported, with the
+/// position or JSON pointer of the value and the driveway and track element it belongs to. The
+/// diagnostics refer to `file`.
+pub fn parse_input(file: &Path, text: &str) -> Result<InterlockingRepr, Vec<Diagnostic>> {
+    let input: Value =
+        serde_json::from_str(text).map_err(|e| vec![json_diagnostic(file, &e, None)])?;
+
+    let errors = validate(text);
+    if !errors.is_empty() {
+        return Err(errors
+            .into_iter()
+            .map(|error| validation_diagnostic(file, &input, error))
+            .collect());
+    }
+
+    match &input {
+        Value::Array(_) => deserialize::<Vec<DrivewayRepr>>(file, text, &input).map(Into::into),
+        Value::Object(object) if object.contains_key("version") => {
+            deserialize::<NormalizedInput>(file, text, &input).and_then(|normalized| {
+                normalized
+                    .try_into()
+                    .map_err(|e| generation_diagnostics(Some(Source { file, text }), &e))
+            })
+        }
+        _ => deserialize::<InterlockingObject>(file, text, &input).map(Into::into),
+    }
+}
+
+/// The diagnostics for an error found while generating, one for every problem it contains.
+/// Problems in a driveway point to the value in `source` they were found in, with its line and
+/// column.
+pub fn generation_diagnostics(source: Option<Source>, error: &GenerationError) -> Vec<Diagnostic> {
+    let file = source.map(|source| source.file);
+    let input: Option<Value> = source.and_then(|source| serde_json::from_str(source.text).ok());
+    let diagnostic = |driveway: Option<&String>,
+                      element: Option<&String>,
+                      location: Option<&Location>,
+                      message: String| {
+        let pointer = input
+            .as_ref()
+            .zip(location)
+            .and_then(|(input, location)| location_pointer(input, location));
+        let position = source
+            .zip(pointer.as_deref())
+            .and_then(|(source, pointer)| pointer_position(source.text, pointer));
+        Diagnostic {
+            position,
+            pointer,
+            driveway: driveway.cloned(),
+            element: element.cloned(),
+            ..Diagnostic::new(file, message)
+        }
+    };
+
+    match error {
+        GenerationError::DuplicateTrackElement(inconsistencies) => inconsistencies
+            .iter()
+            .map(|inconsistency| {
+                diagnostic(
+                    Some(&inconsistency.driveway),
+                    Some(&inconsistency.id),
+                    Some(&inconsistency.location),
+                    format!(
+                        "differs in {} from its description in driveway {}",
+                        inconsistency.fields.join(", "),
+                        inconsistency.first_driveway
+                    ),
+                )
+            })
+            .collect(),
+        GenerationError::UnsupportedSignalStates(unsupported) => unsupported
+            .iter()
+            .map(|unsupported| {
+                diagnostic(
+                    Some(&unsupported.driveway),
+                    Some(&unsupported.signal),
+                    Some(&unsupported.location),
+                    format!("the signal {}", unsupported.problem),
+                )
+            })
+            .collect(),
+        GenerationError::InvalidInitialState(problems) => problems
+            .iter()
+            .map(|problem| {
+                diagnostic(
+                    None,
+                    None,
+                    None,
+                    format!("Invalid initial state: {problem}"),
+                )
+            })
+            .collect(),
+        GenerationError::UnknownDriveway(driveway) => {
+            vec![diagnostic(Some(driveway), None, None, error.to_string())]
+        }
+        GenerationError::SpeedTooLow {
+            driveway, location, ..
+        } => vec![diagnostic(
+            Some(driveway),
+            None,
+            Some(location),
+            error.to_string(),
+        )],
+        GenerationError::UnsupportedSpeed {
+            driveway,
+            signal: element,
+            location,
+            ..
+        }
+        | GenerationError::UnknownTrackElement {
+            driveway,
+            element,
+            location,
+        }
+        | GenerationError::NotASignal {
+            driveway,
+            element,
+            location,
+        }
+        | GenerationError::MismatchedTargetState {
+            driveway,
+            element,
+            location,
+        }
+        | GenerationError::UnconvertibleSignalState {
+            driveway,
+            signal: element,
+            location,
+        }
+        | GenerationError::NoStartSignalState {
+            driveway,
+            signal: element,
+            location,
+        } => vec![diagnostic(
+            Some(driveway),
+            Some(element),
+            Some(location),
+            error.to_string(),
+        )],
+        GenerationError::InvalidJson(_)
+        | GenerationError::UnsupportedInputVersion(_)
+        | GenerationError::DerivedAspectsNeedGrpc => {
+            vec![diagnostic(None, None, None, error.to_string())]
+        }
+        // The problem is in another file than the input
+        GenerationError::UnreadableFile { .. } => vec![Diagnostic::new(None, error.to_string())],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{aspects, generate};
+
+    fn signal(id: &str, main: &str) -> Value {
+        json!({
+            "type": "signal",
+            "uuid": id,
+            "supported_states": {"main": ["hp0", "ks1"]},
+            "state": {"main": main},
+        })
+    }
+
+    #[test]
+    fn pointers_are_found_in_the_text() {
+        let text = "{\n  \"x\": \"}\",\n  \"a/b\": [1, {\"c\": [true]}, \"\\\"\"],\n  \"d\": 2\n}";
+        assert_eq!(pointer_position(text, "/d"), Some((4, 8)));
+        assert_eq!(pointer_position(text, "/a~1b/1/c/0"), Some((3, 21)));
+        assert_eq!(pointer_position(text, "/a~1b/2"), Some((3, 29)));
+        assert_eq!(pointer_position(text, "/a~1b/3"), None);
+        assert_eq!(pointer_position(text, "/y"), None);
+    }
+
+    #[test]
+    fn generation_diagnostics_point_to_the_value() {
+        let text = serde_json::to_string_pretty(&json!({
+            "driveways": [{
+                "start_signal": signal("A", "hp0"),
+                "end_signal": signal("C", "hp0"),
+                "states": [signal("A", "ks1"), signal("C", "ks2")],
+                "max_speed": 5,
+            }]
+        }))
+        .unwrap();
+        let source = Source {
+            file: Path::new("routes.json"),
+            text: &text,
+        };
+        let mut interlocking = parse_input(source.file, &text).unwrap();
+
+        let mut errors = generate::check(&interlocking);
+        errors.extend(aspects::apply_speed_limits(&mut interlocking).err());
+        let locations: Vec<_> = errors
+            .iter()
+            .flat_map(|error| generation_diagnostics(Some(source), error))
+            .map(|diagnostic| (diagnostic.position, diagnostic.pointer))
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                (Some((45, 9)), Some("/driveways/0/states/1".to_string())),
+                (Some((17, 20)), Some("/driveways/0/max_speed".to_string()))
+            ]
+        );
+    }
+}
@@ -1,32 +1,199 @@
+use std::{collections::BTreeMap, fmt::Display};
+
 use proc_macro2::TokenStream;
 use quote::quote;
-use serde::Deserialize;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Metadata, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::generate::{uuid_to_var_name, GenerationError};
+use crate::{
+    generate::{uuid_to_var_name, GenerationError},
+    normalized::NormalizedInput,
+};
 
+/// Types that can be turned into the code that creates them in the generated interlocking.
+/// Realizing fails if the input describes a value the interlocking cannot represent.
 pub trait Realize {
-    fn realize(&self) -> TokenStream;
+    fn realize(&self) -> Result<TokenStream, GenerationError>;
 }
 
+/// The input of the generator. It is either a plain list of driveways, an object that
+/// additionally contains an explicit conflict table and generator options, or the normalized
+/// format with an element catalog (see [`NormalizedInput`]).
 #[derive(Deserialize, Debug, PartialEq)]
+#[serde(try_from = "InterlockingInput")]
+pub struct InterlockingRepr {
+    pub driveways: Vec<DrivewayRepr>,
+    /// Pairs of driveways that must not be set at the same time
+    pub conflicts: Vec<[DrivewayRef; 2]>,
+    pub conflict_mode: ConflictMode,
+    /// Derive the aspects of start signals and distant signals from the next main signal. Only
+    /// the gRPC control station updates derived aspects.
+    pub derive_aspects: bool,
+    pub initial_state: InitialState,
+}
+
+impl JsonSchema for InterlockingRepr {
+    fn schema_name() -> String {
+        "InterlockingInput".to_string()
+    }
+
+    // schemars does not follow `#[serde(try_from)]`, so describe the input directly
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        InterlockingInput::json_schema(gen)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum InterlockingInput {
+    Driveways(Vec<DrivewayRepr>),
+    Normalized(NormalizedInput),
+    Object(InterlockingObject),
+}
+
+/// The input as an object with an explicit conflict table and generator options
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InterlockingObject {
+    pub driveways: Vec<DrivewayRepr>,
+    #[serde(default)]
+    pub conflicts: Vec<[DrivewayRef; 2]>,
+    #[serde(default)]
+    pub conflict_mode: ConflictMode,
+    /// Derive the aspects of start and distant signals from the next main signal. Only the gRPC
+    /// control station updates derived aspects.
+    #[serde(default)]
+    pub derive_aspects: bool,
+    #[serde(default)]
+    pub initial_state: InitialState,
+}
+
+impl TryFrom<InterlockingInput> for InterlockingRepr {
+    type Error = GenerationError;
+
+    fn try_from(input: InterlockingInput) -> Result<Self, Self::Error> {
+        match input {
+            InterlockingInput::Driveways(driveways) => Ok(driveways.into()),
+            InterlockingInput::Normalized(input) => input.try_into(),
+            InterlockingInput::Object(object) => Ok(object.into()),
+        }
+    }
+}
+
+impl From<InterlockingObject> for InterlockingRepr {
+    fn from(object: InterlockingObject) -> Self {
+        let InterlockingObject {
+            driveways,
+            conflicts,
+            conflict_mode,
+            derive_aspects,
+            initial_state,
+        } = object;
+        InterlockingRepr {
+            driveways,
+            conflicts,
+            conflict_mode,
+            derive_aspects,
+            initial_state,
+        }
+    }
+}
+
+impl From<Vec<DrivewayRepr>> for InterlockingRepr {
+    fn from(driveways: Vec<DrivewayRepr>) -> Self {
+        InterlockingRepr {
+            driveways,
+            conflicts: vec![],
+            conflict_mode: ConflictMode::default(),
+            derive_aspects: false,
+            initial_state: InitialState::default(),
+        }
+    }
+}
+
+/// The states the track elements are in when the interlocking starts, by element ID.
+/// Elements that are not listed start in their default state.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct InitialState {
+    #[serde(default)]
+    pub points: BTreeMap<String, PointState>,
+    #[serde(default)]
+    pub signals: BTreeMap<String, SignalState>,
+    #[serde(default)]
+    pub vacancy_sections: BTreeMap<String, VacancySectionState>,
+}
+
+/// How explicit conflicts are combined with the computed ones
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictMode {
+    /// Add the explicit conflicts to the computed ones
+    #[default]
+    Merge,
+    /// Only use the explicit conflicts, e.g. from a reviewed conflict table
+    Replace,
+}
+
+#[derive(Deserialize, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DrivewayRepr {
     pub start_signal: TrackElement,
     pub end_signal: TrackElement,
     pub states: Vec<TrackElement>,
+    /// The maximum speed in km/h, shown by Zs3 at the start signal and announced by Zs3v
+    #[serde(default)]
+    pub max_speed: Option<u16>,
+    /// Driveways that must not be set at the same time as this one
+    #[serde(default)]
+    pub conflicts: Option<Vec<DrivewayRef>>,
 }
 
 impl DrivewayRepr {
     pub fn id(&self) -> String {
-        format!(
-            "{}_{}",
-            uuid_to_var_name(self.start_signal.id()),
-            uuid_to_var_name(self.end_signal.id())
-        )
+        driveway_id(self.start_signal.id(), self.end_signal.id())
+    }
+
+    /// How the driveway is referred to in errors, by the IDs of its signals
+    pub fn name(&self) -> String {
+        format!("{} - {}", self.start_signal.id(), self.end_signal.id())
+    }
+}
+
+/// Refers to a driveway by its start and end signal
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DrivewayRef {
+    pub start_signal: String,
+    pub end_signal: String,
+}
+
+impl DrivewayRef {
+    pub fn id(&self) -> String {
+        driveway_id(&self.start_signal, &self.end_signal)
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
-#[serde(tag = "type", rename_all = "snake_case")]
+impl Display for DrivewayRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.start_signal, self.end_signal)
+    }
+}
+
+fn driveway_id(start_signal: &str, end_signal: &str) -> String {
+    format!(
+        "{}_{}",
+        uuid_to_var_name(start_signal),
+        uuid_to_var_name(end_signal)
+    )
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
 pub enum TrackElement {
     Point {
         uuid: String,
@@ -55,13 +222,31 @@ impl TrackElement {
             TrackElement::VacancySection { uuid, .. } => uuid,
         }
     }
-}
 
-impl Realize for TrackElement {
-    fn realize(&self) -> TokenStream {
+    /// The name of a signal if it has one, otherwise the ID of the element
+    pub fn display_name(&self) -> &str {
         match self {
+            TrackElement::Signal {
+                name: Some(name), ..
+            } => name,
+            _ => self.id(),
+        }
+    }
+}
+
+impl TrackElement {
+    /// Create the element in its initial state
+    pub fn realize_initial(
+        &self,
+        initial_state: &InitialState,
+    ) -> Result<TokenStream, GenerationError> {
+        Ok(match self {
             TrackElement::Point { uuid, .. } => {
-                quote! {track_element::point::Point::new_arc(track_element::point::PointState::default(), #uuid.to_string())}
+                let state = match initial_state.points.get(uuid) {
+                    Some(state) => state.realize()?,
+                    None => quote! {track_element::point::PointState::default()},
+                };
+                quote! {track_element::point::Point::new_arc(#state, #uuid.to_string())}
             }
             TrackElement::Signal {
                 uuid,
@@ -69,13 +254,17 @@ impl Realize for TrackElement {
                 supported_states,
                 ..
             } => {
-                let supported_states = supported_states.realize();
+                let supported_states = supported_states.realize()?;
                 let name = match name {
                     Some(name) => quote! { Some(#name.to_string()) },
                     None => quote! { None },
                 };
+                let state = match initial_state.signals.get(uuid) {
+                    Some(state) => state.realize()?,
+                    None => quote! {track_element::signal::SignalState::default()},
+                };
                 quote! {
-                    track_element::signal::Signal::new_arc(track_element::signal::SignalState::default(), #supported_states, #uuid.to_string(), #name)
+                    track_element::signal::Signal::new_arc(#state, #supported_states, #uuid.to_string(), #name)
                 }
             }
             TrackElement::VacancySection {
@@ -90,92 +279,314 @@ impl Realize for TrackElement {
                         quote! { #var.clone() }
                     })
                     .collect();
+                let state = match initial_state.vacancy_sections.get(uuid) {
+                    Some(state) => state.realize()?,
+                    None => quote! {track_element::vacancy_section::VacancySectionState::default()},
+                };
 
                 quote! {
-                    track_element::vacancy_section::VacancySection::new_arc(#uuid.to_string(), track_element::vacancy_section::VacancySectionState::default(), vec![#(#prev_signals),*])
+                    track_element::vacancy_section::VacancySection::new_arc(#uuid.to_string(), #state, vec![#(#prev_signals),*])
                 }
             }
-        }
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub struct SignalState {
     pub main: MainSignalState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional: Option<AdditionalSignalState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub zs3: Option<AdditionalSignalZs3Symbol>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub zs3v: Option<AdditionalSignalZs3Symbol>,
 }
 
 impl Realize for SignalState {
-    fn realize(&self) -> TokenStream {
-        let main = self.main.realize();
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let main = self.main.realize()?;
+        let additional = match &self.additional {
+            Some(additional) => additional.realize()?,
+            None => quote! {track_element::signal::AdditionalSignalState::Off},
+        };
         let zs3 = match &self.zs3 {
-            Some(zs3) => zs3.realize(),
-            None => AdditionalSignalZs3Symbol(0).realize(),
+            Some(zs3) => zs3.realize()?,
+            None => AdditionalSignalZs3Symbol(0).realize()?,
         };
         let zs3v = match &self.zs3v {
-            Some(zs3v) => zs3v.realize(),
-            None => AdditionalSignalZs3Symbol(0).realize(),
+            Some(zs3v) => zs3v.realize()?,
+            None => AdditionalSignalZs3Symbol(0).realize()?,
         };
-        quote! {
-            track_element::signal::SignalState::new(#main, track_element::signal::AdditionalSignalState::Off, #zs3, #zs3v)
-        }
+        Ok(quote! {
+            track_element::signal::SignalState::new(#main, #additional, #zs3, #zs3v)
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub struct SupportedSignalStates {
     pub main: Vec<MainSignalState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional: Option<Vec<AdditionalSignalState>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub zs3: Option<Vec<AdditionalSignalZs3Symbol>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub zs3v: Option<Vec<AdditionalSignalZs3Symbol>>,
 }
 
+impl SupportedSignalStates {
+    /// Check whether a signal with these supported states can show the given additional
+    /// signal state. Every signal can turn its additional signals off.
+    pub fn supports_additional(
+        &self,
+        additional: &AdditionalSignalState,
+    ) -> Result<bool, GenerationError> {
+        let additional: track_element::signal::AdditionalSignalState = additional.try_into()?;
+        if additional == track_element::signal::AdditionalSignalState::Off {
+            return Ok(true);
+        }
+        for supported in self.additional.iter().flatten() {
+            let supported: track_element::signal::AdditionalSignalState = supported.try_into()?;
+            if supported == additional {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check whether a signal with these supported states can show the given main aspect
+    pub fn supports_main(&self, main: &MainSignalState) -> Result<bool, GenerationError> {
+        let main: track_element::signal::MainSignalState = main.try_into()?;
+        for supported in &self.main {
+            let supported: track_element::signal::MainSignalState = supported.try_into()?;
+            if supported == main {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check whether a signal with these supported states can show the given Zs3 symbol.
+    /// Every signal can turn its Zs3 off.
+    pub fn supports_zs3(&self, symbol: &AdditionalSignalZs3Symbol) -> bool {
+        symbol.0 == 0
+            || self
+                .zs3
+                .iter()
+                .flatten()
+                .any(|supported| supported == symbol)
+    }
+
+    /// Check whether a signal with these supported states can show the given Zs3v symbol.
+    /// Every signal can turn its Zs3v off.
+    pub fn supports_zs3v(&self, symbol: &AdditionalSignalZs3Symbol) -> bool {
+        symbol.0 == 0
+            || self
+                .zs3v
+                .iter()
+                .flatten()
+                .any(|supported| supported == symbol)
+    }
+}
+
 impl Realize for SupportedSignalStates {
-    fn realize(&self) -> TokenStream {
-        let main = self.main.iter().map(Realize::realize);
-        let zs3 = match &self.zs3 {
-            Some(states) => states.iter().map(Realize::realize).collect(),
-            None => vec![],
-        };
-        let zs3v = match &self.zs3v {
-            Some(states) => states.iter().map(Realize::realize).collect(),
-            None => vec![],
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let main = self
+            .main
+            .iter()
+            .map(Realize::realize)
+            .collect::<Result<Vec<_>, _>>()?;
+        let zs3 = self
+            .zs3
+            .iter()
+            .flatten()
+            .map(Realize::realize)
+            .collect::<Result<Vec<_>, _>>()?;
+        let zs3v = self
+            .zs3v
+            .iter()
+            .flatten()
+            .map(Realize::realize)
+            .collect::<Result<Vec<_>, _>>()?;
+        let additional = match &self.additional {
+            Some(states) => {
+                let states = states
+                    .iter()
+                    .map(Realize::realize)
+                    .collect::<Result<Vec<_>, _>>()?;
+                quote! {.additional(&mut vec![#(#states),*])}
+            }
+            None => quote! {},
         };
 
-        quote! {
-            track_element::signal::SupportedSignalStates::default().main(&mut vec![#(#main),*]).zs3(&mut vec![#(#zs3),*]).zs3v(&mut vec![#(#zs3v),*])
-        }
+        Ok(quote! {
+            track_element::signal::SupportedSignalStates::default().main(&mut vec![#(#main),*]).zs3(&mut vec![#(#zs3),*]).zs3v(&mut vec![#(#zs3v),*])#additional
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// A main signal aspect as written in the input. Parsing ignores case as well as `_`, `-`
+/// and spaces, and `+` may be used instead of `plus`. The accepted spellings are:
+///
+/// - `Hp0`: `hp0`
+/// - `Hp0PlusSh1`: `hp0_plus_sh1`, `hp0+sh1`, `hp0_sh1`
+/// - `Hp0WithDrivingIndicator`: `hp0_with_driving_indicator`, `hp0_driving_indicator`
+/// - `Hp0Hv`: `hp0_hv`
+/// - `Hp1`: `hp1`
+/// - `Hp2`: `hp2`
+/// - `Ks1`: `ks1`
+/// - `Ks1Flashing`: `ks1_flashing`, `ks1_blinking`
+/// - `Ks1FlashingWithAdditionalLight`: `ks1_flashing_with_additional_light`,
+///   `ks1_flashing_additional_light`
+/// - `Ks2`: `ks2`
+/// - `Ks2WithAdditionalLight`: `ks2_with_additional_light`, `ks2_additional_light`
+/// - `Sh1`: `sh1`
+/// - `Vr0`: `vr0`
+/// - `Vr1`: `vr1`
+/// - `Vr2`: `vr2`
+/// - `IdLight`: `id_light`, `kennlicht`
+/// - `Off`: `off`, `dark`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct MainSignalState(pub String);
 
+/// The accepted spellings of the main signal aspects, as listed above
+const MAIN_SIGNAL_STATES: [(&str, track_element::signal::MainSignalState); 25] = {
+    use track_element::signal::MainSignalState::*;
+    [
+        ("hp0", Hp0),
+        ("hp0_plus_sh1", Hp0PlusSh1),
+        ("hp0+sh1", Hp0PlusSh1),
+        ("hp0_sh1", Hp0PlusSh1),
+        ("hp0_with_driving_indicator", Hp0WithDrivingIndicator),
+        ("hp0_driving_indicator", Hp0WithDrivingIndicator),
+        ("hp0_hv", Hp0Hv),
+        ("hp1", Hp1),
+        ("hp2", Hp2),
+        ("ks1", Ks1),
+        ("ks1_flashing", Ks1Flashing),
+        ("ks1_blinking", Ks1Flashing),
+        (
+            "ks1_flashing_with_additional_light",
+            Ks1FlashingWithAdditionalLight,
+        ),
+        (
+            "ks1_flashing_additional_light",
+            Ks1FlashingWithAdditionalLight,
+        ),
+        ("ks2", Ks2),
+        ("ks2_with_additional_light", Ks2WithAdditionalLight),
+        ("ks2_additional_light", Ks2WithAdditionalLight),
+        ("sh1", Sh1),
+        ("vr0", Vr0),
+        ("vr1", Vr1),
+        ("vr2", Vr2),
+        ("id_light", IdLight),
+        ("kennlicht", IdLight),
+        ("off", Off),
+        ("dark", Off),
+    ]
+};
+
+/// Normalize the spelling of a main signal aspect for comparison
+fn normalized_aspect(aspect: &str) -> String {
+    aspect
+        .to_ascii_lowercase()
+        .replace('+', "plus")
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .collect()
+}
+
+/// A regular expression that matches any of the spellings regardless of case. If `separators`
+/// is given, it also matches the spellings with these characters between the letters and `+`
+/// instead of `plus`, like `normalized_aspect`.
+fn spelling_pattern<'a>(spellings: impl Iterator<Item = &'a str>, separators: &str) -> String {
+    let letters = |text: &str| -> Vec<String> {
+        text.chars()
+            .map(|c| match c {
+                'a'..='z' => format!("[{c}{}]", c.to_ascii_uppercase()),
+                c => c.to_string(),
+            })
+            .collect()
+    };
+    let plus = if separators.is_empty() {
+        "plus".to_string()
+    } else {
+        format!("(?:\\+|{})", letters("plus").join(separators))
+    };
+    let mut alternatives: Vec<_> = spellings
+        .map(|spelling| {
+            spelling
+                .split("plus")
+                .map(|part| letters(part).join(separators))
+                .collect::<Vec<_>>()
+                .join(&format!("{separators}{plus}{separators}"))
+        })
+        .collect();
+    alternatives.sort();
+    alternatives.dedup();
+    format!("^{separators}(?:{}){separators}$", alternatives.join("|"))
+}
+
+/// A string schema for an enumeration that is parsed leniently. The spellings are listed as
+/// examples, and the pattern accepts every spelling the parser accepts.
+fn spellings_schema(description: &str, spellings: Vec<&str>, pattern: String) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern),
+            ..Default::default()
+        })),
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_string()),
+            examples: spellings.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl JsonSchema for MainSignalState {
+    fn schema_name() -> String {
+        "MainSignalState".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let normalized: Vec<_> = MAIN_SIGNAL_STATES
+            .iter()
+            .map(|(spelling, _)| normalized_aspect(spelling))
+            .collect();
+        spellings_schema(
+            "A main signal aspect. Parsing ignores case as well as `_`, `-` and spaces, and `+` may be used instead of `plus`.",
+            MAIN_SIGNAL_STATES.iter().map(|(spelling, _)| *spelling).collect(),
+            spelling_pattern(normalized.iter().map(String::as_str), "[_\\- ]*"),
+        )
+    }
+}
+
 impl TryInto<track_element::signal::MainSignalState> for &MainSignalState {
     type Error = GenerationError;
 
     fn try_into(self) -> Result<track_element::signal::MainSignalState, Self::Error> {
-        let lower = self.0.to_ascii_lowercase();
-        match lower.as_str() {
-            "ks1" => Ok(track_element::signal::MainSignalState::Ks1),
-            "ks2" => Ok(track_element::signal::MainSignalState::Ks2),
-            "hp0" => Ok(track_element::signal::MainSignalState::Hp0),
-            "hp1" => Ok(track_element::signal::MainSignalState::Hp1),
-            "hp2" => Ok(track_element::signal::MainSignalState::Hp2),
-            "sh1" => Ok(track_element::signal::MainSignalState::Sh1),
-            e => Err(GenerationError::InvalidJson(format!(
-                "Unknown main signal state `{e}`"
-            ))),
-        }
+        let aspect = normalized_aspect(&self.0);
+        MAIN_SIGNAL_STATES
+            .iter()
+            .find(|(spelling, _)| normalized_aspect(spelling) == aspect)
+            .map(|(_, aspect)| *aspect)
+            .ok_or_else(|| {
+                GenerationError::InvalidJson(format!("Unknown main signal state `{}`", self.0))
+            })
     }
 }
 
 impl Realize for MainSignalState {
-    fn realize(&self) -> TokenStream {
-        let state: track_element::signal::MainSignalState = self.try_into().unwrap();
-        match state {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let state: track_element::signal::MainSignalState = self.try_into()?;
+        Ok(match state {
             track_element::signal::MainSignalState::Hp0 => {
                 quote! {track_element::signal::MainSignalState::Hp0}
             }
@@ -227,12 +638,109 @@ impl Realize for MainSignalState {
             track_element::signal::MainSignalState::Off => {
                 quote! {track_element::signal::MainSignalState::Off}
             }
+        })
+    }
+}
+
+/// An additional signal state as written in the input: `off`, `zs1`, `zs6`, `zs7`, `zs8` or
+/// `zs13`, ignoring case
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AdditionalSignalState(pub String);
+
+const ADDITIONAL_SIGNAL_STATES: [(&str, track_element::signal::AdditionalSignalState); 6] = {
+    use track_element::signal::AdditionalSignalState::*;
+    [
+        ("off", Off),
+        ("zs1", Zs1),
+        ("zs6", Zs6),
+        ("zs7", Zs7),
+        ("zs8", Zs8),
+        ("zs13", Zs13),
+    ]
+};
+
+impl JsonSchema for AdditionalSignalState {
+    fn schema_name() -> String {
+        "AdditionalSignalState".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let spellings = ADDITIONAL_SIGNAL_STATES.map(|(spelling, _)| spelling);
+        spellings_schema(
+            "An additional signal state, ignoring case",
+            spellings.to_vec(),
+            spelling_pattern(spellings.into_iter(), ""),
+        )
+    }
+}
+
+impl TryInto<track_element::signal::AdditionalSignalState> for &AdditionalSignalState {
+    type Error = GenerationError;
+
+    fn try_into(self) -> Result<track_element::signal::AdditionalSignalState, Self::Error> {
+        ADDITIONAL_SIGNAL_STATES
+            .iter()
+            .find(|(spelling, _)| spelling.eq_ignore_ascii_case(&self.0))
+            .map(|(_, state)| *state)
+            .ok_or_else(|| {
+                GenerationError::InvalidJson(format!(
+                    "Unknown additional signal state `{}`",
+                    self.0
+                ))
+            })
+    }
+}
+
+impl Realize for AdditionalSignalState {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let state: track_element::signal::AdditionalSignalState = self.try_into()?;
+        Ok(match state {
+            track_element::signal::AdditionalSignalState::Off => {
+                quote! {track_element::signal::AdditionalSignalState::Off}
+            }
+            track_element::signal::AdditionalSignalState::Zs1 => {
+                quote! {track_element::signal::AdditionalSignalState::Zs1}
+            }
+            track_element::signal::AdditionalSignalState::Zs6 => {
+                quote! {track_element::signal::AdditionalSignalState::Zs6}
+            }
+            track_element::signal::AdditionalSignalState::Zs7 => {
+                quote! {track_element::signal::AdditionalSignalState::Zs7}
+            }
+            track_element::signal::AdditionalSignalState::Zs8 => {
+                quote! {track_element::signal::AdditionalSignalState::Zs8}
+            }
+            track_element::signal::AdditionalSignalState::Zs13 => {
+                quote! {track_element::signal::AdditionalSignalState::Zs13}
+            }
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct AdditionalSignalZs3Symbol(#[schemars(range(max = 16))] u8);
+
+impl AdditionalSignalZs3Symbol {
+    /// The symbol for the highest speed up to the given one, in steps of 10 km/h.
+    /// `None` if the speed is too low to be shown.
+    pub fn for_speed(speed: u16) -> Option<Self> {
+        match speed / 10 {
+            0 => None,
+            symbol => Some(Self(symbol.min(16) as u8)),
         }
     }
+
+    /// The speed in km/h that the symbol stands for
+    pub fn speed(&self) -> u16 {
+        u16::from(self.0) * 10
+    }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
-pub struct AdditionalSignalZs3Symbol(u8);
+impl Display for AdditionalSignalZs3Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 impl TryInto<track_element::signal::AdditionalSignalZs3Symbol> for &AdditionalSignalZs3Symbol {
     type Error = GenerationError;
@@ -244,9 +752,9 @@ impl TryInto<track_element::signal::AdditionalSignalZs3Symbol> for &AdditionalSi
 }
 
 impl Realize for AdditionalSignalZs3Symbol {
-    fn realize(&self) -> TokenStream {
-        let symbol: track_element::signal::AdditionalSignalZs3Symbol = self.try_into().unwrap();
-        match symbol {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        let symbol: track_element::signal::AdditionalSignalZs3Symbol = self.try_into()?;
+        Ok(match symbol {
             track_element::signal::AdditionalSignalZs3Symbol::OFF => {
                 quote! {track_element::signal::AdditionalSignalZs3Symbol::OFF}
             }
@@ -298,11 +806,11 @@ impl Realize for AdditionalSignalZs3Symbol {
             track_element::signal::AdditionalSignalZs3Symbol::SIXTEEN => {
                 quote! {track_element::signal::AdditionalSignalZs3Symbol::SIXTEEN}
             }
-        }
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum PointState {
     Left,
@@ -310,15 +818,15 @@ pub enum PointState {
 }
 
 impl Realize for PointState {
-    fn realize(&self) -> TokenStream {
-        match self {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        Ok(match self {
             PointState::Left => quote! {track_element::point::PointState::Left},
             PointState::Right => quote! {track_element::point::PointState::Right},
-        }
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum VacancySectionState {
     Free,
@@ -326,14 +834,80 @@ pub enum VacancySectionState {
 }
 
 impl Realize for VacancySectionState {
-    fn realize(&self) -> TokenStream {
-        match self {
+    fn realize(&self) -> Result<TokenStream, GenerationError> {
+        Ok(match self {
             VacancySectionState::Free => {
                 quote! {track_element::vacancy_section::VacancySectionState::Free}
             }
             VacancySectionState::Occupied => {
-                quote! {track_element::vacancy_section::VacancySectionState::Free}
+                quote! {track_element::vacancy_section::VacancySectionState::Occupied}
             }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(aspect: &str) -> Result<String, GenerationError> {
+        let state = MainSignalState(aspect.to_string());
+        Ok(state.realize()?.to_string())
+    }
+
+    #[test]
+    fn main_signal_state_aliases() {
+        let aliases = [
+            ("Hp0PlusSh1", "Hp0PlusSh1"),
+            ("hp0_plus_sh1", "Hp0PlusSh1"),
+            ("HP0+SH1", "Hp0PlusSh1"),
+            ("hp0 sh1", "Hp0PlusSh1"),
+            ("Hp0-Driving-Indicator", "Hp0WithDrivingIndicator"),
+            ("KS1 blinking", "Ks1Flashing"),
+            (
+                "ks1_flashing_additional_light",
+                "Ks1FlashingWithAdditionalLight",
+            ),
+            ("Ks2WithAdditionalLight", "Ks2WithAdditionalLight"),
+            ("Kennlicht", "IdLight"),
+            ("dark", "Off"),
+        ];
+        for (alias, aspect) in aliases {
+            assert_eq!(
+                parse(alias).unwrap(),
+                format!("track_element :: signal :: MainSignalState :: {aspect}"),
+                "{alias}"
+            );
         }
     }
+
+    #[test]
+    fn every_listed_spelling_is_accepted() {
+        for (spelling, _) in MAIN_SIGNAL_STATES {
+            assert!(parse(spelling).is_ok(), "{spelling}");
+            assert!(parse(&spelling.to_ascii_uppercase()).is_ok(), "{spelling}");
+        }
+    }
+
+    #[test]
+    fn unknown_main_signal_states_are_rejected() {
+        for aspect in ["", "hp", "ks3", "hp0 plus", "sh1+hp0"] {
+            assert!(parse(aspect).is_err(), "{aspect}");
+        }
+    }
+
+    #[test]
+    fn zs3_symbols_for_speeds() {
+        let symbol = |speed| AdditionalSignalZs3Symbol::for_speed(speed).map(|symbol| symbol.0);
+        assert_eq!(symbol(0), None);
+        assert_eq!(symbol(9), None);
+        assert_eq!(symbol(10), Some(1));
+        assert_eq!(symbol(65), Some(6));
+        assert_eq!(symbol(160), Some(16));
+        assert_eq!(symbol(250), Some(16));
+        assert_eq!(
+            AdditionalSignalZs3Symbol::for_speed(65).map(|symbol| symbol.speed()),
+            Some(60)
+        );
+    }
 }
@@ -2,48 +2,212 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
 };
 use thiserror::Error;
+use track_element::signal::{AdditionalSignalState as Additional, MainSignalState as Main};
 
 use crate::{
-    driveway::{DrivewayRepr, Realize, TrackElement},
+    aspects::{shows_stop, DerivedSignal},
+    conflicts::compute_conflicting_driveways,
+    driveway::{DrivewayRepr, InitialState, InterlockingRepr, Realize, TrackElement},
+    normalized::NORMALIZED_VERSION,
     ControlStation,
 };
 
 #[derive(Clone, Debug, Error)]
 pub enum GenerationError {
-    #[error("Two track elements with the same ID, but different types exist.")]
-    DuplicateTrackElement,
-    #[error("The driveway JSON was not valid.")]
+    #[error(
+        "Some track elements are described differently by different driveways:{}",
+        .0.iter().map(|duplicate| format!("\n  {duplicate}")).collect::<String>()
+    )]
+    DuplicateTrackElement(Vec<InconsistentTrackElement>),
+    #[error("The driveway JSON was not valid: {0}")]
     InvalidJson(String),
+    #[error("The conflict table refers to the unknown driveway {0}.")]
+    UnknownDriveway(String),
+    #[error(
+        "Some driveways need signal states that their signals cannot show:{}",
+        .0.iter().map(|unsupported| format!("\n  {unsupported}")).collect::<String>()
+    )]
+    UnsupportedSignalStates(Vec<UnsupportedSignalState>),
+    #[error(
+        "The initial state is invalid:{}",
+        .0.iter().map(|problem| format!("\n  {problem}")).collect::<String>()
+    )]
+    InvalidInitialState(Vec<String>),
+    #[error("Version {0} of the normalized input format is not supported, expected version {NORMALIZED_VERSION}.")]
+    UnsupportedInputVersion(u32),
+    #[error("Driveway {driveway} refers to the unknown track element {element}.")]
+    UnknownTrackElement {
+        driveway: String,
+        element: String,
+        location: Location,
+    },
+    #[error(
+        "Driveway {driveway} refers to track element {element} as a signal, but it is not one."
+    )]
+    NotASignal {
+        driveway: String,
+        element: String,
+        location: Location,
+    },
+    #[error("Driveway {driveway} gives track element {element} a target state that does not match its type.")]
+    MismatchedTargetState {
+        driveway: String,
+        element: String,
+        location: Location,
+    },
+    #[error("Driveway {driveway} gives signal {signal} a state that differs from its target state, which the normalized format cannot express.")]
+    UnconvertibleSignalState {
+        driveway: String,
+        signal: String,
+        location: Location,
+    },
+    #[error(
+        "The maximum speed of {speed} km/h of driveway {driveway} is too low to be shown by Zs3."
+    )]
+    SpeedTooLow {
+        driveway: String,
+        speed: u16,
+        location: Location,
+    },
+    #[error("Driveway {driveway} has a maximum speed, but no target state for its start signal {signal} that could show it with Zs3.")]
+    NoStartSignalState {
+        driveway: String,
+        signal: String,
+        location: Location,
+    },
+    #[error("Signal {signal} cannot show {indicator} {symbol} for the maximum speed of {speed} km/h of driveway {driveway}.")]
+    UnsupportedSpeed {
+        driveway: String,
+        signal: String,
+        /// `Zs3` or `Zs3v`
+        indicator: &'static str,
+        symbol: String,
+        speed: u16,
+        location: Location,
+    },
+    #[error("Derived aspects are only updated by the gRPC control station. Generate a gRPC control station or set `derive_aspects` to false.")]
+    DerivedAspectsNeedGrpc,
+    #[error("Could not read the {kind} file {path}: {message}")]
+    UnreadableFile {
+        /// `topology` or `placement`
+        kind: &'static str,
+        path: String,
+        message: String,
+    },
 }
 
+/// Where a problem is in the input: the index of a driveway and the path of the value inside
+/// it, e.g. `/states/2` or `/max_speed`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    pub driveway: usize,
+    pub path: String,
+}
+
+impl Location {
+    pub fn new(driveway: usize, path: impl Into<String>) -> Self {
+        Location {
+            driveway,
+            path: path.into(),
+        }
+    }
+}
+
+/// A track element that two driveways describe differently
+#[derive(Clone, Debug, PartialEq)]
+pub struct InconsistentTrackElement {
+    pub id: String,
+    /// The driveway whose description of the element is used
+    pub first_driveway: String,
+    pub driveway: String,
+    /// The fields that differ, e.g. `name` or `supported_states.zs3`
+    pub fields: Vec<&'static str>,
+    /// The description that differs
+    pub location: Location,
+}
+
+impl Display for InconsistentTrackElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Track element {}: driveways {} and {} differ in {}",
+            self.id,
+            self.first_driveway,
+            self.driveway,
+            self.fields.join(", ")
+        )
+    }
+}
+
+/// A signal state that a driveway needs, but its signal cannot show
+#[derive(Clone, Debug)]
+pub struct UnsupportedSignalState {
+    pub driveway: String,
+    pub signal: String,
+    /// What is wrong with the signal, e.g. `does not support Zs3 7` or
+    /// `shows the unknown main aspect ks9`
+    pub problem: String,
+    pub location: Location,
+}
+
+impl Display for UnsupportedSignalState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Driveway {}: signal {} {}",
+            self.driveway, self.signal, self.problem
+        )
+    }
+}
+
+/// The variable name of a track element in the generated code. Characters that are not allowed
+/// in identifiers, like the `-` of UUIDs, and `_` itself are replaced by their code in hex
+/// between two `_`, so that different IDs never get the same name. `A-1` becomes `_A_2d_1`.
 pub fn uuid_to_var_name(uuid: &str) -> TokenStream {
-    format_ident!("_{}", uuid.replace('-', "_")).to_token_stream()
+    let name: String = uuid
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_string()
+            } else {
+                format!("_{:x}_", c as u32)
+            }
+        })
+        .collect();
+    format_ident!("_{}", name).to_token_stream()
 }
 
 /// Create new TrackElements and add them to a BTreeMap
-fn realize_element(element: &TrackElement) -> TokenStream {
+fn realize_element(
+    element: &TrackElement,
+    initial_state: &InitialState,
+) -> Result<TokenStream, GenerationError> {
     let var_name = uuid_to_var_name(element.id());
-    let realized = element.realize();
-    quote! {let #var_name = #realized;}
+    let realized = element.realize_initial(initial_state)?;
+    Ok(quote! {let #var_name = #realized;})
 }
 
-fn realize_driveway(driveway: &DrivewayRepr) -> TokenStream {
+fn realize_driveway(driveway: &DrivewayRepr) -> Result<TokenStream, GenerationError> {
     let point_states: Vec<_> = driveway
         .states
         .iter()
         .filter_map(|e| {
             if let TrackElement::Point { uuid, state, .. } = e {
                 let point = uuid_to_var_name(uuid);
-                let state = state.realize();
-                Some(quote! {(#point.clone(), #state)})
+                Some(
+                    state
+                        .realize()
+                        .map(|state| quote! {(#point.clone(), #state)}),
+                )
             } else {
                 None
             }
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
     let signal_states: Vec<_> = driveway
         .states
@@ -51,13 +215,16 @@ fn realize_driveway(driveway: &DrivewayRepr) -> TokenStream {
         .filter_map(|e| {
             if let TrackElement::Signal { uuid, state, .. } = e {
                 let signal = uuid_to_var_name(uuid);
-                let state = state.realize();
-                Some(quote! {(#signal.clone(), #state)})
+                Some(
+                    state
+                        .realize()
+                        .map(|state| quote! {(#signal.clone(), #state)}),
+                )
             } else {
                 None
             }
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
     let vacancy_section_states: Vec<_> = driveway
         .states
@@ -65,69 +232,351 @@ fn realize_driveway(driveway: &DrivewayRepr) -> TokenStream {
         .filter_map(|e| {
             if let TrackElement::VacancySection { uuid, state, .. } = e {
                 let vacancy_section = uuid_to_var_name(uuid);
-                let state = state.realize();
-                Some(quote! {(#vacancy_section.clone(), #state)})
+                Some(
+                    state
+                        .realize()
+                        .map(|state| quote! {(#vacancy_section.clone(), #state)}),
+                )
             } else {
                 None
             }
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
     let start_signal = uuid_to_var_name(driveway.start_signal.id());
     let end_signal = uuid_to_var_name(driveway.end_signal.id());
     let driveway_name = format_ident!("{}", driveway.id());
 
-    quote! {
+    Ok(quote! {
         let point_states = vec![#(#point_states),*];
         let signal_states = vec![#(#signal_states),*];
         let vacancy_section_states = vec![#(#vacancy_section_states),*];
         let target_state = track_element::driveway::DrivewayState::new(point_states, signal_states, vacancy_section_states);
         let mut #driveway_name = Arc::new(RwLock::new(track_element::driveway::Driveway::new(vec![], target_state, #start_signal.clone(), #end_signal.clone())));
         driveway_manager.add(#driveway_name.clone());
-    }
+    })
 }
 
-fn collect_track_elements(
-    routes: &Vec<DrivewayRepr>,
-) -> Result<BTreeMap<String, TrackElement>, GenerationError> {
-    let mut track_elements: BTreeMap<String, TrackElement> = BTreeMap::new();
-    for route in routes {
-        for el in &route.states {
-            let id = el.id();
-            if !track_elements.contains_key(id) {
-                track_elements.insert(id.to_string(), el.clone());
-            } else {
-                let existing_track_element = track_elements.get(id).unwrap();
-                match (existing_track_element, el) {
-                    (TrackElement::Point { .. }, TrackElement::Point { .. }) => {}
-                    (TrackElement::Signal { .. }, TrackElement::Signal { .. }) => {}
-                    (TrackElement::VacancySection { .. }, TrackElement::VacancySection { .. }) => {}
-                    _ => return Err(GenerationError::DuplicateTrackElement),
+/// Check the state of every signal in every driveway against the states the signal supports
+/// and report all violations at once, including unknown aspects
+fn validate_signal_states(routes: &[DrivewayRepr]) -> Result<(), GenerationError> {
+    let mut unsupported = vec![];
+    // Signals whose supported states were checked already, since every driveway describes them
+    let mut checked_signals = HashSet::new();
+    for (i, route) in routes.iter().enumerate() {
+        let signals = route
+            .states
+            .iter()
+            .enumerate()
+            .map(|(j, state)| (format!("/states/{j}"), state))
+            .chain([
+                ("/start_signal".to_string(), &route.start_signal),
+                ("/end_signal".to_string(), &route.end_signal),
+            ]);
+        for (path, signal) in signals {
+            let TrackElement::Signal {
+                uuid,
+                supported_states,
+                state,
+                ..
+            } = signal
+            else {
+                continue;
+            };
+            let mut report = |problem: String| {
+                unsupported.push(UnsupportedSignalState {
+                    driveway: route.name(),
+                    signal: uuid.clone(),
+                    problem,
+                    location: Location::new(i, path.clone()),
+                })
+            };
+
+            let check_supported = checked_signals.insert(uuid.clone());
+            let mut supported_main = vec![];
+            for main in &supported_states.main {
+                match main.try_into() {
+                    Ok(main) => supported_main.push(main),
+                    Err(_) if check_supported => report(format!(
+                        "lists the unknown main aspect {} as supported",
+                        main.0
+                    )),
+                    Err(_) => {}
+                }
+            }
+            let mut supported_additional = vec![Additional::Off];
+            for additional in supported_states.additional.iter().flatten() {
+                match additional.try_into() {
+                    Ok(additional) => supported_additional.push(additional),
+                    Err(_) if check_supported => report(format!(
+                        "lists the unknown additional signal state {} as supported",
+                        additional.0
+                    )),
+                    Err(_) => {}
                 }
             }
 
+            match TryInto::<Main>::try_into(&state.main) {
+                Ok(main) if supported_main.contains(&main) => {}
+                Ok(_) => report(format!("does not support the main aspect {}", state.main.0)),
+                Err(_) => report(format!("shows the unknown main aspect {}", state.main.0)),
+            }
+            if let Some(additional) = &state.additional {
+                match TryInto::<Additional>::try_into(additional) {
+                    Ok(state) if supported_additional.contains(&state) => {}
+                    Ok(_) => report(format!(
+                        "does not support the additional signal state {}",
+                        additional.0
+                    )),
+                    Err(_) => report(format!(
+                        "shows the unknown additional signal state {}",
+                        additional.0
+                    )),
+                }
+            }
+            if let Some(zs3) = &state.zs3 {
+                if !supported_states.supports_zs3(zs3) {
+                    report(format!("does not support Zs3 {zs3}"));
+                }
+            }
+            if let Some(zs3v) = &state.zs3v {
+                if !supported_states.supports_zs3v(zs3v) {
+                    report(format!("does not support Zs3v {zs3v}"));
+                }
+            }
+        }
+    }
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(GenerationError::UnsupportedSignalStates(unsupported))
+    }
+}
+
+/// Check that the initial state only refers to known elements of the right type, that the
+/// signals support their initial states and that the initial state is safe. Since no driveway
+/// is set when the interlocking starts, no point is locked, so no signal may show proceed into
+/// a driveway over a point.
+fn validate_initial_state(
+    interlocking: &InterlockingRepr,
+    track_elements: &BTreeMap<String, TrackElement>,
+) -> Result<(), GenerationError> {
+    let InitialState {
+        points,
+        signals,
+        vacancy_sections,
+    } = &interlocking.initial_state;
+    let mut problems = vec![];
+
+    for id in points.keys() {
+        if !matches!(track_elements.get(id), Some(TrackElement::Point { .. })) {
+            problems.push(format!("{id} is not a point"));
+        }
+    }
+    for id in vacancy_sections.keys() {
+        if !matches!(
+            track_elements.get(id),
+            Some(TrackElement::VacancySection { .. })
+        ) {
+            problems.push(format!("{id} is not a vacancy section"));
+        }
+    }
+
+    for (id, state) in signals {
+        let Some(TrackElement::Signal {
+            supported_states, ..
+        }) = track_elements.get(id)
+        else {
+            problems.push(format!("{id} is not a signal"));
+            continue;
+        };
+        // Unknown supported states are reported with the signal states of the driveways
+        if TryInto::<Main>::try_into(&state.main).is_err() {
+            problems.push(format!(
+                "Signal {id} shows the unknown main aspect {}",
+                state.main.0
+            ));
+        } else if let Ok(false) = supported_states.supports_main(&state.main) {
+            problems.push(format!(
+                "Signal {id} does not support the main aspect {}",
+                state.main.0
+            ));
+        }
+        if let Some(additional) = &state.additional {
+            if TryInto::<Additional>::try_into(additional).is_err() {
+                problems.push(format!(
+                    "Signal {id} shows the unknown additional signal state {}",
+                    additional.0
+                ));
+            } else if let Ok(false) = supported_states.supports_additional(additional) {
+                problems.push(format!(
+                    "Signal {id} does not support the additional signal state {}",
+                    additional.0
+                ));
+            }
+        }
+        if let Some(zs3) = &state.zs3 {
+            if !supported_states.supports_zs3(zs3) {
+                problems.push(format!("Signal {id} does not support Zs3 {zs3}"));
+            }
+        }
+        if let Some(zs3v) = &state.zs3v {
+            if !supported_states.supports_zs3v(zs3v) {
+                problems.push(format!("Signal {id} does not support Zs3v {zs3v}"));
+            }
+        }
+
+        if !matches!(shows_stop(state), Ok(false)) {
+            continue;
+        }
+        let unlocked_point = interlocking
+            .driveways
+            .iter()
+            .filter(|driveway| driveway.start_signal.id() == id)
+            .find_map(|driveway| {
+                driveway.states.iter().find_map(|element| match element {
+                    TrackElement::Point { uuid, .. } => Some((driveway, uuid)),
+                    _ => None,
+                })
+            });
+        if let Some((driveway, point)) = unlocked_point {
+            problems.push(format!(
+                "Signal {id} must not show {} in the initial state, since point {point} of driveway {} is not locked",
+                state.main.0,
+                driveway.name()
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(GenerationError::InvalidInitialState(problems))
+    }
+}
+
+/// The fields in which two descriptions of the same track element differ. The target states
+/// are not compared, since they depend on the driveway.
+fn differing_fields(element: &TrackElement, other: &TrackElement) -> Vec<&'static str> {
+    let mut fields = vec![];
+    match (element, other) {
+        (TrackElement::Point { .. }, TrackElement::Point { .. }) => {}
+        (
+            TrackElement::Signal {
+                name,
+                supported_states,
+                ..
+            },
+            TrackElement::Signal {
+                name: other_name,
+                supported_states: other_supported_states,
+                ..
+            },
+        ) => {
+            if name != other_name {
+                fields.push("name");
+            }
+            if supported_states.main != other_supported_states.main {
+                fields.push("supported_states.main");
+            }
+            if supported_states.additional != other_supported_states.additional {
+                fields.push("supported_states.additional");
+            }
+            if supported_states.zs3 != other_supported_states.zs3 {
+                fields.push("supported_states.zs3");
+            }
+            if supported_states.zs3v != other_supported_states.zs3v {
+                fields.push("supported_states.zs3v");
+            }
+        }
+        (
+            TrackElement::VacancySection {
+                previous_signals, ..
+            },
+            TrackElement::VacancySection {
+                previous_signals: other_previous_signals,
+                ..
+            },
+        ) => {
+            let ids = previous_signals.iter().map(TrackElement::id);
+            if !ids.eq(other_previous_signals.iter().map(TrackElement::id)) {
+                fields.push("previous_signals");
+            }
+        }
+        _ => fields.push("type"),
+    }
+    fields
+}
+
+/// Collect every track element of the driveways once. All descriptions of an element have to
+/// agree, otherwise every difference is reported.
+pub fn collect_track_elements(
+    routes: &[DrivewayRepr],
+) -> Result<BTreeMap<String, TrackElement>, GenerationError> {
+    // Each element with the driveway that described it first
+    let mut track_elements: BTreeMap<String, (TrackElement, String)> = BTreeMap::new();
+    let mut inconsistencies: Vec<InconsistentTrackElement> = vec![];
+    for (i, route) in routes.iter().enumerate() {
+        let driveway = route.name();
+        let mut elements = vec![];
+        for (j, el) in route.states.iter().enumerate() {
+            elements.push((format!("/states/{j}"), el));
             if let TrackElement::VacancySection {
                 previous_signals, ..
             } = el
             {
-                for signal in previous_signals {
-                    if !track_elements.contains_key(signal.id()) {
-                        track_elements.insert(signal.id().to_string(), signal.clone());
+                elements.extend(
+                    previous_signals
+                        .iter()
+                        .enumerate()
+                        .map(|(k, signal)| (format!("/states/{j}/previous_signals/{k}"), signal)),
+                );
+            }
+        }
+        elements.extend([
+            ("/start_signal".to_string(), &route.start_signal),
+            ("/end_signal".to_string(), &route.end_signal),
+        ]);
+
+        for (path, el) in elements {
+            match track_elements.get(el.id()) {
+                None => {
+                    track_elements.insert(el.id().to_string(), (el.clone(), driveway.clone()));
+                }
+                Some((existing, first_driveway)) => {
+                    let inconsistency = InconsistentTrackElement {
+                        id: el.id().to_string(),
+                        first_driveway: first_driveway.clone(),
+                        driveway: driveway.clone(),
+                        fields: differing_fields(existing, el),
+                        location: Location::new(i, path),
+                    };
+                    // A driveway may describe an element several times, e.g. as its start
+                    // signal and in its target state
+                    let reported = inconsistencies.iter().any(|reported| {
+                        (&reported.id, &reported.driveway, &reported.fields)
+                            == (
+                                &inconsistency.id,
+                                &inconsistency.driveway,
+                                &inconsistency.fields,
+                            )
+                    });
+                    if !inconsistency.fields.is_empty() && !reported {
+                        inconsistencies.push(inconsistency);
                     }
                 }
             }
         }
-        if !track_elements.contains_key(route.start_signal.id()) {
-            track_elements.insert(
-                route.start_signal.id().to_string(),
-                route.start_signal.clone(),
-            );
-        }
-        if !track_elements.contains_key(route.end_signal.id()) {
-            track_elements.insert(route.end_signal.id().to_string(), route.end_signal.clone());
-        }
     }
-    Ok(track_elements)
+
+    if !inconsistencies.is_empty() {
+        return Err(GenerationError::DuplicateTrackElement(inconsistencies));
+    }
+    Ok(track_elements
+        .into_iter()
+        .map(|(id, (element, _))| (id, element))
+        .collect())
 }
 
 fn generate_setup_tokens(
@@ -143,7 +592,7 @@ fn generate_setup_tokens(
 }
 
 fn generate_conflicting_driveway_tokens(
-    conflicting_driveways: HashMap<String, Vec<String>>,
+    conflicting_driveways: &HashMap<String, Vec<String>>,
 ) -> TokenStream {
     let driveways = conflicting_driveways.iter().map(|(driveway, conflicts)| {
         let driveway = format_ident!("{driveway}");
@@ -159,7 +608,9 @@ fn generate_conflicting_driveway_tokens(
     quote! {{#(#driveways)*}}
 }
 
-pub fn generate_tests(routes: &Vec<DrivewayRepr>) -> Result<String, GenerationError> {
+pub fn generate_tests(interlocking: &InterlockingRepr) -> Result<String, GenerationError> {
+    let routes = &interlocking.driveways;
+    validate_signal_states(routes)?;
     let mut track_elements: Vec<TrackElement> =
         collect_track_elements(routes)?.into_values().collect();
 
@@ -168,59 +619,269 @@ pub fn generate_tests(routes: &Vec<DrivewayRepr>) -> Result<String, GenerationEr
         _ => Ordering::Equal,
     });
 
-    let track_element_tokens: Vec<_> = track_elements.iter().map(realize_element).collect();
+    // The tests always start from the default state of the elements
+    let initial_state = InitialState::default();
+    let track_element_tokens: Vec<_> = track_elements
+        .iter()
+        .map(|element| realize_element(element, &initial_state))
+        .collect::<Result<_, _>>()?;
 
-    let mut conflicting_driveways = HashMap::new();
-    for driveway in routes {
-        let mut conflicts = vec![];
-        for other in routes {
-            if driveway != other
-                && driveway
-                    .states
-                    .iter()
-                    .any(|t| other.states.iter().any(|o| t.id() == o.id()))
-            {
-                conflicts.push(other.id());
-            }
-        }
-        conflicting_driveways.insert(driveway.id(), conflicts);
-    }
+    let conflicting_driveways = compute_conflicting_driveways(interlocking)?;
 
-    let driveway_tokens: _ = routes
+    let driveway_tokens = routes
         .iter()
         .map(realize_driveway)
-        .collect::<Vec<TokenStream>>();
+        .collect::<Result<Vec<TokenStream>, _>>()?;
 
     let setup_tokens = generate_setup_tokens(track_element_tokens, driveway_tokens);
+    let conflicting_driveway_tokens = generate_conflicting_driveway_tokens(&conflicting_driveways);
+    let setup_tokens = quote! {
+        #setup_tokens
+        #conflicting_driveway_tokens
+    };
+
+    let driveway_tests = routes
+        .iter()
+        .map(|driveway| generate_driveway_test(driveway, &setup_tokens))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // One test per pair of driveways, since conflicts apply in both directions
+    let mut conflict_tests = vec![];
+    for (i, driveway) in routes.iter().enumerate() {
+        for other in &routes[i + 1..] {
+            if conflicting_driveways[&driveway.id()].contains(&other.id())
+                || conflicting_driveways[&other.id()].contains(&driveway.id())
+            {
+                conflict_tests.push(generate_conflict_test(driveway, other, &setup_tokens));
+            } else {
+                conflict_tests.push(generate_no_conflict_test(driveway, other, &setup_tokens));
+            }
+        }
+    }
 
     let tokens = quote! {
+        #![allow(non_snake_case, unused_mut, unused_variables)]
+
         extern crate track_element;
 
         use std::collections::BTreeMap;
         use std::sync::{Arc, RwLock};
-        use std::panic;
         use track_element::driveway::DrivewayManager;
+        use track_element::TrackElement;
 
+        /// Check that the elements of a driveway have reached their target states
+        fn assert_driveway_state(
+            driveway_manager: &DrivewayManager,
+            point_states: &[(&str, track_element::point::PointState)],
+            signal_states: &[(&str, track_element::signal::MainSignalState)],
+        ) {
+            let state = driveway_manager.state();
+            for (id, expected) in point_states {
+                let (_, actual) = state
+                    .points()
+                    .iter()
+                    .find(|(point, _)| point.read().unwrap().id() == *id)
+                    .expect("Point should be part of the driveway manager");
+                assert_eq!(actual, expected, "Point {} is in the wrong state", id);
+            }
+            for (id, expected) in signal_states {
+                let (_, actual) = state
+                    .signals()
+                    .iter()
+                    .find(|(signal, _)| signal.read().unwrap().id() == *id)
+                    .expect("Signal should be part of the driveway manager");
+                assert_eq!(&actual.main(), expected, "Signal {} is in the wrong state", id);
+            }
+        }
+
+        #(#driveway_tests)*
+
+        #(#conflict_tests)*
+    };
+
+    Ok(tokens.to_string())
+}
+
+fn set_driveway_tokens(driveway: &DrivewayRepr) -> TokenStream {
+    let start_signal = driveway.start_signal.id();
+    let end_signal = driveway.end_signal.id();
+    quote! {driveway_manager.set_driveway(#start_signal, #end_signal)}
+}
+
+/// A driveway can be set from a fresh state and its elements reach their target states
+fn generate_driveway_test(
+    driveway: &DrivewayRepr,
+    setup_tokens: &TokenStream,
+) -> Result<TokenStream, GenerationError> {
+    let test_name = format_ident!("test_set_driveway_{}", driveway.id());
+    let set_driveway = set_driveway_tokens(driveway);
+
+    let point_states = driveway
+        .states
+        .iter()
+        .filter_map(|e| match e {
+            TrackElement::Point { uuid, state } => {
+                Some(state.realize().map(|state| quote! {(#uuid, #state)}))
+            }
+            _ => None,
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let signal_states = driveway
+        .states
+        .iter()
+        .filter_map(|e| match e {
+            TrackElement::Signal { uuid, state, .. } => {
+                Some(state.main.realize().map(|main| quote! {(#uuid, #main)}))
+            }
+            _ => None,
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(quote! {
         #[test]
-        fn test_known_driveway() {
+        fn #test_name() {
             #setup_tokens
-            assert!(driveway_manager.set_driveway("A","C").is_ok());
+            assert!(#set_driveway.is_ok());
+            assert_driveway_state(&driveway_manager, &[#(#point_states),*], &[#(#signal_states),*]);
         }
+    })
+}
+
+/// Each driveway is refused while the other one is set
+fn generate_conflict_test(
+    driveway: &DrivewayRepr,
+    other: &DrivewayRepr,
+    setup_tokens: &TokenStream,
+) -> TokenStream {
+    let test_name = format_ident!("test_conflict_{}_{}", driveway.id(), other.id());
+    let set_driveway = set_driveway_tokens(driveway);
+    let set_other = set_driveway_tokens(other);
 
+    quote! {
+        #[test]
+        fn #test_name() {
+            {
+                #setup_tokens
+                assert!(#set_driveway.is_ok());
+                assert!(#set_other.is_err());
+            }
+            {
+                #setup_tokens
+                assert!(#set_other.is_ok());
+                assert!(#set_driveway.is_err());
+            }
+        }
+    }
+}
+
+/// Both driveways can be set at the same time
+fn generate_no_conflict_test(
+    driveway: &DrivewayRepr,
+    other: &DrivewayRepr,
+    setup_tokens: &TokenStream,
+) -> TokenStream {
+    let test_name = format_ident!("test_no_conflict_{}_{}", driveway.id(), other.id());
+    let set_driveway = set_driveway_tokens(driveway);
+    let set_other = set_driveway_tokens(other);
+
+    quote! {
         #[test]
-        fn test_unknown_driveway() {
+        fn #test_name() {
             #setup_tokens
-            assert!(driveway_manager.set_driveway("A","X").is_err());
+            assert!(#set_driveway.is_ok());
+            assert!(#set_other.is_ok());
         }
+    }
+}
+
+/// Read the topology or placement file of the gRPC control station
+fn read_layout_file(kind: &'static str, path: &str) -> Result<String, GenerationError> {
+    std::fs::read_to_string(path).map_err(|e| GenerationError::UnreadableFile {
+        kind,
+        path: path.to_string(),
+        message: e.to_string(),
+    })
+}
 
+/// The vacancy sections each point may lie in, i.e. the vacancy sections that every driveway
+/// over the point runs through. If that is more than one, all of them are kept, so that the
+/// point is checked against each of them. Points without such a vacancy section are left out.
+pub fn point_sections(routes: &[DrivewayRepr]) -> BTreeMap<String, Vec<String>> {
+    let vacancy_sections = |driveway: &DrivewayRepr| -> BTreeSet<String> {
+        driveway
+            .states
+            .iter()
+            .filter(|element| matches!(element, TrackElement::VacancySection { .. }))
+            .map(|element| element.id().to_string())
+            .collect()
     };
 
-    Ok(tokens.to_string())
+    let mut candidates: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for driveway in routes {
+        let sections = vacancy_sections(driveway);
+        for point in &driveway.states {
+            let TrackElement::Point { uuid, .. } = point else {
+                continue;
+            };
+            candidates
+                .entry(uuid.clone())
+                .and_modify(|candidates| candidates.retain(|id| sections.contains(id)))
+                .or_insert_with(|| sections.clone());
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter(|(_, sections)| !sections.is_empty())
+        .map(|(point, sections)| (point, sections.into_iter().collect()))
+        .collect()
+}
+
+/// The conflicting driveways of each driveway for the gRPC control station, with every
+/// driveway given by the IDs of its start and end signal
+fn conflict_table_tokens(
+    routes: &[DrivewayRepr],
+    conflicting_driveways: &HashMap<String, Vec<String>>,
+) -> TokenStream {
+    let signals: HashMap<_, _> = routes
+        .iter()
+        .map(|route| (route.id(), (route.start_signal.id(), route.end_signal.id())))
+        .collect();
+    let conflict_table: BTreeMap<_, Vec<_>> = conflicting_driveways
+        .iter()
+        .filter(|(_, conflicts)| !conflicts.is_empty())
+        .filter_map(|(driveway, conflicts)| {
+            let conflicts = conflicts.iter().filter_map(|c| signals.get(c)).collect();
+            Some((signals.get(driveway)?, conflicts))
+        })
+        .collect();
+    if conflict_table.is_empty() {
+        return quote! {};
+    }
+
+    let entries = conflict_table.iter().map(|((start, end), conflicts)| {
+        let conflicts = conflicts
+            .iter()
+            .map(|(start, end)| quote! {(#start.to_string(), #end.to_string())});
+        quote! {((#start.to_string(), #end.to_string()), vec![#(#conflicts),*])}
+    });
+    quote! {
+        .conflicting_driveways(BTreeMap::from([#(#entries),*]))
+    }
 }
 
-fn generate_control_station(control_station: &ControlStation) -> TokenStream {
-    match control_station {
-        ControlStation::Cli => quote! {
+fn generate_control_station(
+    control_station: &ControlStation,
+    routes: &[DrivewayRepr],
+    conflicting_driveways: &HashMap<String, Vec<String>>,
+    derived_signals: &[DerivedSignal],
+) -> Result<TokenStream, GenerationError> {
+    Ok(match control_station {
+        // The command line control station of track_element cannot re-evaluate derived aspects
+        ControlStation::Cli { .. } if !derived_signals.is_empty() => {
+            return Err(GenerationError::DerivedAspectsNeedGrpc)
+        }
+        ControlStation::Cli { .. } => quote! {
             let control_station = track_element::control_station::ControlStation::new(driveway_manager);
             control_station.start();
         },
@@ -228,62 +889,121 @@ fn generate_control_station(control_station: &ControlStation) -> TokenStream {
             addr,
             topology,
             placement,
+            release_delay,
+            ..
         } => {
-            let topology = std::fs::read_to_string(topology)
-                .expect("Topology should point to a valid JSON file");
-            let placement = std::fs::read_to_string(placement)
-                .expect("Placement should point to a valid JSON file");
+            let topology = read_layout_file("topology", topology)?;
+            let placement = read_layout_file("placement", placement)?;
+
+            let point_sections = point_sections(routes);
+            let point_sections = if point_sections.is_empty() {
+                quote! {}
+            } else {
+                let (points, sections): (Vec<_>, Vec<_>) = point_sections.into_iter().unzip();
+                quote! {
+                    .point_sections(BTreeMap::from([#((#points.to_string(), vec![#(#sections.to_string()),*])),*]))
+                }
+            };
+
+            let conflict_table = conflict_table_tokens(routes, conflicting_driveways);
+
+            let release_delay = match release_delay {
+                Some(seconds) => quote! {
+                    .release_delay(std::time::Duration::from_secs(#seconds))
+                },
+                None => quote! {},
+            };
+
+            let derived_aspects = if derived_signals.is_empty() {
+                quote! {}
+            } else {
+                let derived_signals = derived_signals
+                    .iter()
+                    .map(Realize::realize)
+                    .collect::<Result<Vec<_>, _>>()?;
+                quote! {
+                    .derived_aspects(vec![#(#derived_signals),*])
+                }
+            };
 
             quote! {
-                let mut control_station = grpc_control_station::ControlStation::new(driveway_manager, #topology, #placement);
+                let mut control_station = grpc_control_station::ControlStation::new(driveway_manager, #topology, #placement)
+                    #point_sections
+                    #release_delay
+                    #conflict_table
+                    #derived_aspects;
 
                 let addr = #addr.parse().unwrap();
                 control_station.listen(addr).await.unwrap();
             }
         }
+    })
+}
+
+/// Run every check that generating the interlocking runs and return all errors, instead of
+/// stopping at the first one
+pub fn check(interlocking: &InterlockingRepr) -> Vec<GenerationError> {
+    let routes = &interlocking.driveways;
+    let mut errors = vec![];
+    errors.extend(validate_signal_states(routes).err());
+    match collect_track_elements(routes) {
+        Ok(track_elements) => {
+            errors.extend(validate_initial_state(interlocking, &track_elements).err());
+            // Unknown aspects were reported above
+            if errors.is_empty() {
+                errors.extend(track_elements.values().filter_map(|element| {
+                    realize_element(element, &interlocking.initial_state).err()
+                }));
+            }
+        }
+        Err(error) => errors.push(error),
     }
+    errors.extend(
+        routes
+            .iter()
+            .filter_map(|route| realize_driveway(route).err()),
+    );
+    errors.extend(compute_conflicting_driveways(interlocking).err());
+    errors
 }
 
-pub fn generate(routes: &Vec<DrivewayRepr>, cs: ControlStation) -> Result<String, GenerationError> {
-    let mut track_elements: Vec<TrackElement> =
-        collect_track_elements(routes)?.into_values().collect();
+pub fn generate(
+    interlocking: &InterlockingRepr,
+    derived_signals: &[DerivedSignal],
+    cs: ControlStation,
+) -> Result<String, GenerationError> {
+    let routes = &interlocking.driveways;
+    validate_signal_states(routes)?;
+    let track_elements = collect_track_elements(routes)?;
+    validate_initial_state(interlocking, &track_elements)?;
+    let mut track_elements: Vec<TrackElement> = track_elements.into_values().collect();
 
     track_elements.sort_by(|a, b| match (a, b) {
         (_, TrackElement::VacancySection { .. }) => Ordering::Less,
         _ => Ordering::Equal,
     });
 
-    let track_element_tokens: Vec<_> = track_elements.iter().map(realize_element).collect();
+    let track_element_tokens: Vec<_> = track_elements
+        .iter()
+        .map(|element| realize_element(element, &interlocking.initial_state))
+        .collect::<Result<_, _>>()?;
 
-    let mut conflicting_driveways = HashMap::new();
-    for driveway in routes {
-        let mut conflicts = vec![];
-        for other in routes {
-            if driveway != other
-                && driveway
-                    .states
-                    .iter()
-                    .any(|t| other.states.iter().any(|o| t.id() == o.id()))
-            {
-                conflicts.push(other.id());
-            }
-        }
-        conflicting_driveways.insert(driveway.id(), conflicts);
-    }
+    let conflicting_driveways = compute_conflicting_driveways(interlocking)?;
 
-    let driveway_tokens: _ = routes
+    let driveway_tokens = routes
         .iter()
         .map(realize_driveway)
-        .collect::<Vec<TokenStream>>();
+        .collect::<Result<Vec<TokenStream>, _>>()?;
 
     let setup_tokens = generate_setup_tokens(track_element_tokens, driveway_tokens);
 
-    let conflicting_driveway_tokens = generate_conflicting_driveway_tokens(conflicting_driveways);
+    let conflicting_driveway_tokens = generate_conflicting_driveway_tokens(&conflicting_driveways);
 
-    let control_station = generate_control_station(&cs);
+    let control_station =
+        generate_control_station(&cs, routes, &conflicting_driveways, derived_signals)?;
 
     let (main_qualifier, main_attr) = match cs {
-        ControlStation::Cli => (quote! {}, quote! {}),
+        ControlStation::Cli { .. } => (quote! {}, quote! {}),
         ControlStation::Grpc { .. } => (quote! {async}, quote! {#[tokio::main]}),
     };
 
@@ -311,3 +1031,169 @@ pub fn generate(routes: &Vec<DrivewayRepr>, cs: ControlStation) -> Result<String
 
     Ok(tokens.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::driveway::DrivewayRef;
+
+    fn signal(id: &str, supported: &[&str], state: Value) -> Value {
+        json!({
+            "type": "signal",
+            "uuid": id,
+            "supported_states": {"main": supported},
+            "state": state,
+        })
+    }
+
+    #[test]
+    fn variable_names_are_unique() {
+        let ids = ["A", "A-1", "A_1", "A.1", "A__1", "A_5f_1", "1 A", "Ä"];
+        let names: Vec<_> = ids
+            .iter()
+            .map(|id| uuid_to_var_name(id).to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "_A",
+                "_A_2d_1",
+                "_A_5f_1",
+                "_A_2e_1",
+                "_A_5f__5f_1",
+                "_A_5f_5f_5f_1",
+                "_1_20_A",
+                "__c4_"
+            ]
+        );
+        let driveways: HashSet<_> = [("A_", "C"), ("A", "_C"), ("A", "C")]
+            .iter()
+            .map(|(start, end)| {
+                DrivewayRef {
+                    start_signal: start.to_string(),
+                    end_signal: end.to_string(),
+                }
+                .id()
+            })
+            .collect();
+        assert_eq!(driveways.len(), 3);
+    }
+
+    #[test]
+    fn points_lie_in_the_vacancy_sections_all_driveways_share() {
+        let point = |state| json!({"type": "point", "uuid": "W1", "state": state});
+        let section = |id| json!({"type": "vacancy_section", "uuid": id, "state": "free", "previous_signals": []});
+        let driveway = |end: &str, states: Vec<Value>| {
+            json!({
+                "start_signal": signal("A", &["hp0"], json!({"main": "hp0"})),
+                "end_signal": signal(end, &["hp0"], json!({"main": "hp0"})),
+                "states": states,
+            })
+        };
+        let routes: Vec<DrivewayRepr> = serde_json::from_value(json!([
+            driveway("B", vec![point("left"), section("T1"), section("T2")]),
+            driveway("C", vec![point("right"), section("T1"), section("T3")]),
+            driveway(
+                "D",
+                vec![
+                    json!({"type": "point", "uuid": "W2", "state": "left"}),
+                    section("T4"),
+                    section("T5")
+                ]
+            ),
+        ]))
+        .unwrap();
+
+        // W2 has only one driveway, so either of its vacancy sections could hold it and it is
+        // checked against both
+        assert_eq!(
+            point_sections(&routes),
+            BTreeMap::from([
+                ("W1".to_string(), vec!["T1".to_string()]),
+                ("W2".to_string(), vec!["T4".to_string(), "T5".to_string()]),
+            ])
+        );
+    }
+
+    #[test]
+    fn tests_every_driveway_and_every_pair_once() {
+        let signal = |id| signal(id, &["hp0", "ks1"], json!({"main": "ks1"}));
+        let section = |id| json!({"type": "vacancy_section", "uuid": id, "state": "free", "previous_signals": []});
+        let driveway = |start, end, states: Vec<Value>| json!({"start_signal": signal(start), "end_signal": signal(end), "states": states});
+        let interlocking: InterlockingRepr = serde_json::from_value(json!([
+            driveway(
+                "A",
+                "C",
+                vec![
+                    json!({"type": "point", "uuid": "W1", "state": "left"}),
+                    section("T1")
+                ]
+            ),
+            driveway(
+                "B",
+                "C",
+                vec![
+                    json!({"type": "point", "uuid": "W1", "state": "right"}),
+                    section("T1")
+                ]
+            ),
+            driveway("C", "D", vec![section("T2")]),
+        ]))
+        .unwrap();
+
+        let tests = generate_tests(&interlocking).unwrap();
+        let names: Vec<_> = tests
+            .split("fn ")
+            .skip(1)
+            .filter_map(|test| test.split_once(' ').map(|(name, _)| name))
+            .filter(|name| name.starts_with("test_"))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "test_set_driveway__A__C",
+                "test_set_driveway__B__C",
+                "test_set_driveway__C__D",
+                "test_conflict__A__C__B__C",
+                "test_no_conflict__A__C__C__D",
+                "test_no_conflict__B__C__C__D",
+            ]
+        );
+        assert!(tests.contains(r#"driveway_manager . set_driveway ("A" , "C")"#));
+        assert!(tests.contains(r#"driveway_manager . set_driveway ("B" , "C")"#));
+    }
+
+    #[test]
+    fn reports_every_unsupported_signal_state() {
+        let a = ["hp0", "ks1", "kx9"];
+        let c = ["hp0", "ks1"];
+        let routes: Vec<DrivewayRepr> = serde_json::from_value(json!([{
+            "start_signal": signal("A", &a, json!({"main": "hp0"})),
+            "end_signal": signal("C", &c, json!({"main": "hp0"})),
+            "states": [
+                signal("A", &a, json!({"main": "ks7"})),
+                signal("C", &c, json!({"main": "ks2", "additional": "zs99", "zs3": 7})),
+            ],
+        }]))
+        .unwrap();
+
+        let Err(GenerationError::UnsupportedSignalStates(unsupported)) =
+            validate_signal_states(&routes)
+        else {
+            panic!("the signal states were accepted");
+        };
+        let problems: Vec<_> = unsupported.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            problems,
+            [
+                "Driveway A - C: signal A lists the unknown main aspect kx9 as supported",
+                "Driveway A - C: signal A shows the unknown main aspect ks7",
+                "Driveway A - C: signal C does not support the main aspect ks2",
+                "Driveway A - C: signal C shows the unknown additional signal state zs99",
+                "Driveway A - C: signal C does not support Zs3 7",
+            ]
+        );
+    }
+}
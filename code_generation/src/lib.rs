@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+
+pub mod aspects;
+
+pub mod compare;
+
+pub mod conflicts;
+
+pub mod diagnostic;
+
+pub mod driveway;
+
+pub mod generate;
+
+pub mod normalized;
+
+pub mod table;
+
+pub mod validate;
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ControlStation {
+    /// Build the interlocking with a command line control station
+    #[command()]
+    Cli {
+        /// Where to write the generated interlocking code
+        #[arg(long, short, value_hint = clap::ValueHint::DirPath)]
+        output: PathBuf,
+    },
+    /// Build the interlocking with a gRPC control station and Web UI
+    #[command()]
+    Grpc {
+        /// Where to write the generated interlocking code
+        #[arg(long, short, value_hint = clap::ValueHint::DirPath)]
+        output: PathBuf,
+        /// Address to run the webserver on
+        #[arg(short, long)]
+        addr: String,
+        /// The location of the JSON file containing the topology information
+        #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
+        topology: String,
+        /// The location of the JSON file containing the placement information
+        #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
+        placement: String,
+        /// Release driveways whose approach section is occupied after this many seconds
+        /// instead of refusing the release
+        #[arg(long, value_name = "SECONDS")]
+        release_delay: Option<u64>,
+    },
+}
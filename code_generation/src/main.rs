@@ -1,54 +1,86 @@
 use std::{io::Write, path::PathBuf};
 
+use anyhow::{anyhow, bail, Context};
+
 use clap::{Parser, Subcommand};
-use driveway::{
-    DrivewayRepr, MainSignalState, PointState, SignalState, SupportedSignalStates, TrackElement,
+use code_generation::{
+    aspects, compare, conflicts,
+    diagnostic::{self, Diagnostic, Source},
+    driveway::{
+        DrivewayRepr, InterlockingRepr, MainSignalState, PointState, SignalState,
+        SupportedSignalStates, TrackElement,
+    },
+    generate, normalized,
+    table::{self, TableFormat},
+    validate, ControlStation,
 };
 use fs_extra::dir::CopyOptions;
 
-mod driveway;
-
-mod generate;
-
 #[derive(Debug, Parser)]
 #[command(
     name = "IXL Code Generator",
     about = "A tool to generate exceutable interlockings from JSON"
 )]
 struct Opt {
-    /// Which control station to use - defaults to CLI
+    /// Which control station to use, or which other output to produce
     #[command(subcommand)]
-    control_station: ControlStation,
+    command: Command,
     /// The JSON source for the generator
-    #[arg(value_hint = clap::ValueHint::FilePath, required_unless_present = "example")]
+    #[arg(value_hint = clap::ValueHint::FilePath)]
     input: Option<PathBuf>,
-    /// Where to write the generated interlocking code
-    #[arg(long, short, value_hint = clap::ValueHint::DirPath)]
-    output: PathBuf,
     /// Use the example data provided by this tool (ignores JSON input)
     #[arg(long, short)]
     example: bool,
 }
 
-#[derive(Default, Debug, Clone, Subcommand)]
-pub enum ControlStation {
-    #[default]
-    /// Build the interlocking with a gRPC control station and Web UI
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    #[command(flatten)]
+    ControlStation(ControlStation),
+    /// Export the interlocking table (Verschlusstabelle) instead of generating code
     #[command()]
-    Cli,
-    /// Build the interlocking with a gRPC control station and Web UI
+    Table {
+        /// Where to write the table
+        #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
+        output: PathBuf,
+        /// The format of the exported table
+        #[arg(short, long, value_enum, default_value_t)]
+        format: TableFormat,
+    },
+    /// Compare the interlocking table with a reference table in CSV format and exit with
+    /// an error if they differ
+    #[command()]
+    Compare {
+        /// The reference table
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        reference: PathBuf,
+    },
+    /// Convert the input into the normalized format with an element catalog
     #[command()]
-    Grpc {
-        /// Address to run the webserver on
-        #[arg(short, long)]
-        addr: String,
-        /// The location of the JSON file containing the topology information
-        #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
-        topology: String,
-        /// The location of the JSON file containing the placement information
-        #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
-        placement: String,
+    Convert {
+        /// Where to write the converted input
+        #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
+        output: PathBuf,
     },
+    /// Write the JSON Schema of the input
+    #[command()]
+    Schema {
+        /// Where to write the schema
+        #[arg(long, short, value_hint = clap::ValueHint::FilePath)]
+        output: PathBuf,
+    },
+    /// Run all checks on the input and print every problem that is found, without generating
+    /// anything
+    #[command()]
+    Validate,
+}
+
+/// Print the diagnostics for problems with the input
+fn report(diagnostics: Vec<Diagnostic>) -> anyhow::Error {
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}");
+    }
+    anyhow!("The input is not valid")
 }
 
 fn main() -> anyhow::Result<()> {
@@ -63,11 +95,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             end_signal: TrackElement::Signal {
@@ -77,11 +111,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             states: vec![
@@ -92,11 +128,13 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
                 TrackElement::Point {
@@ -110,14 +148,18 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
             ],
+            max_speed: None,
+            conflicts: None,
         },
         DrivewayRepr {
             start_signal: TrackElement::Signal {
@@ -127,11 +169,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             end_signal: TrackElement::Signal {
@@ -141,11 +185,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             states: vec![
@@ -160,11 +206,13 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
                 TrackElement::Signal {
@@ -174,14 +222,18 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
             ],
+            max_speed: None,
+            conflicts: None,
         },
         DrivewayRepr {
             start_signal: TrackElement::Signal {
@@ -191,11 +243,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             end_signal: TrackElement::Signal {
@@ -205,11 +259,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             states: vec![
@@ -220,11 +276,13 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
                 TrackElement::Point {
@@ -238,14 +296,18 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
             ],
+            max_speed: None,
+            conflicts: None,
         },
         DrivewayRepr {
             start_signal: TrackElement::Signal {
@@ -255,11 +317,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             end_signal: TrackElement::Signal {
@@ -269,11 +333,13 @@ fn main() -> anyhow::Result<()> {
                     main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
                 state: SignalState {
                     main: MainSignalState("Ks1".into()),
                     zs3: None,
                     zs3v: None,
+                    additional: None,
                 },
             },
             states: vec![
@@ -288,11 +354,13 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
                 TrackElement::Signal {
@@ -302,28 +370,116 @@ fn main() -> anyhow::Result<()> {
                         main: vec![MainSignalState("Hp0".into()), MainSignalState("Ks1".into())],
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                     state: SignalState {
                         main: MainSignalState("Ks1".into()),
                         zs3: None,
                         zs3v: None,
+                        additional: None,
                     },
                 },
             ],
+            max_speed: None,
+            conflicts: None,
         },
     ];
 
-    let routes: Vec<_> = if args.example {
-        example_routes
+    if let Command::Schema { output } = &args.command {
+        let schema = validate::input_schema();
+        std::fs::write(output, serde_json::to_string_pretty(&schema)? + "\n")?;
+        return Ok(());
+    }
+
+    let text = if args.example {
+        None
     } else {
-        serde_json::from_str(&std::fs::read_to_string(args.input.clone().unwrap())?)?
+        let input = args
+            .input
+            .as_deref()
+            .context("An input file is required unless --example is given")?;
+        Some(diagnostic::read_text(input).map_err(report)?)
+    };
+    // Problems found while generating are reported with the same details as those in the file
+    let source = args
+        .input
+        .as_deref()
+        .zip(text.as_deref())
+        .map(|(file, text)| Source { file, text });
+    let mut interlocking: InterlockingRepr = match source {
+        Some(Source { file, text }) => diagnostic::parse_input(file, text).map_err(report)?,
+        None => example_routes.into(),
+    };
+    let diagnose =
+        |e: generate::GenerationError| report(diagnostic::generation_diagnostics(source, &e));
+
+    if let Command::Convert { output } = &args.command {
+        let normalized = normalized::normalize(&interlocking).map_err(diagnose)?;
+        std::fs::write(output, serde_json::to_string_pretty(&normalized)?)?;
+        return Ok(());
+    }
+
+    if let Command::Validate = args.command {
+        // Every check runs, even if an earlier one failed, so that all problems are reported
+        let mut errors = vec![];
+        errors.extend(aspects::apply_speed_limits(&mut interlocking).err());
+        errors.extend(aspects::derive_aspects(&mut interlocking).err());
+        errors.extend(generate::check(&interlocking));
+        if !errors.is_empty() {
+            return Err(report(
+                errors
+                    .iter()
+                    .flat_map(|e| diagnostic::generation_diagnostics(source, e))
+                    .collect(),
+            ));
+        }
+        println!("The input is valid");
+        return Ok(());
+    }
+
+    aspects::apply_speed_limits(&mut interlocking).map_err(diagnose)?;
+    let derived_signals = aspects::derive_aspects(&mut interlocking).map_err(diagnose)?;
+
+    for (driveway, other) in conflicts::dropped_conflicts(&interlocking).map_err(diagnose)? {
+        eprintln!(
+            "Warning: driveways {driveway} and {other} share track elements, but the conflict table does not list them as conflicting"
+        );
+    }
+
+    let control_station = match args.command {
+        Command::ControlStation(control_station) => control_station,
+        Command::Convert { .. } | Command::Schema { .. } | Command::Validate => {
+            unreachable!("these commands are handled above")
+        }
+        Command::Table { output, format } => {
+            let rows = table::interlocking_table(&interlocking).map_err(diagnose)?;
+            std::fs::write(output, table::render(&rows, format))?;
+            return Ok(());
+        }
+        Command::Compare { reference } => {
+            let rows = table::interlocking_table(&interlocking).map_err(diagnose)?;
+            let differences = compare::compare(&rows, &std::fs::read_to_string(reference)?)?;
+            if differences.is_empty() {
+                println!("The interlocking table matches the reference table");
+                return Ok(());
+            }
+            for difference in &differences {
+                println!("{difference}");
+            }
+            bail!(
+                "The interlocking table differs from the reference table in {} places",
+                differences.len()
+            );
+        }
     };
+    let (ControlStation::Cli { output } | ControlStation::Grpc { output, .. }) = &control_station;
 
-    let generated = generate::generate(&routes, args.control_station.clone())?;
-    let generated_tests = generate::generate_tests(&routes)?;
+    let generated = generate::generate(&interlocking, &derived_signals, control_station.clone())
+        .map_err(diagnose)?;
+    let generated_tests = generate::generate_tests(&interlocking).map_err(diagnose)?;
 
     let mut output_path = std::env::current_dir()?;
-    output_path.push(&args.output);
+    output_path.push(output);
 
     std::fs::create_dir_all(&output_path)?;
 
@@ -352,7 +508,7 @@ fn main() -> anyhow::Result<()> {
         .spawn()?
         .wait()?;
 
-    if let ControlStation::Grpc { .. } = args.control_station {
+    if let ControlStation::Grpc { .. } = control_station {
         std::process::Command::new("cargo")
             .current_dir(&output_path)
             .args([
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use track_element::signal::{AdditionalSignalState as Additional, MainSignalState as Main};
+
+use crate::{
+    driveway::{
+        ConflictMode, DrivewayRef, DrivewayRepr, InitialState, InterlockingRepr, MainSignalState,
+        PointState, SignalState, SupportedSignalStates, TrackElement, VacancySectionState,
+    },
+    generate::{collect_track_elements, GenerationError, Location},
+};
+
+/// The version of the normalized input format
+pub const NORMALIZED_VERSION: u32 = 1;
+
+/// The normalized input format. Every track element is described once in the element catalog,
+/// and driveways refer to the elements by their ID.
+#[derive(Deserialize, Serialize, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizedInput {
+    /// The version of the format, currently 1
+    pub version: u32,
+    pub elements: Vec<CatalogElement>,
+    pub driveways: Vec<NormalizedDriveway>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<[DrivewayRef; 2]>,
+    #[serde(default)]
+    pub conflict_mode: ConflictMode,
+    /// Derive the aspects of start and distant signals from the next main signal. Only the gRPC
+    /// control station updates derived aspects.
+    #[serde(default)]
+    pub derive_aspects: bool,
+    #[serde(default)]
+    pub initial_state: InitialState,
+}
+
+/// A track element without a state
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum CatalogElement {
+    Point {
+        uuid: String,
+    },
+    Signal {
+        uuid: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        supported_states: SupportedSignalStates,
+    },
+    VacancySection {
+        uuid: String,
+        /// The IDs of the signals in front of the vacancy section
+        #[serde(default)]
+        previous_signals: Vec<String>,
+    },
+}
+
+impl CatalogElement {
+    fn id(&self) -> &str {
+        match self {
+            CatalogElement::Point { uuid } => uuid,
+            CatalogElement::Signal { uuid, .. } => uuid,
+            CatalogElement::VacancySection { uuid, .. } => uuid,
+        }
+    }
+}
+
+/// A driveway that refers to its signals and track elements by ID
+#[derive(Deserialize, Serialize, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizedDriveway {
+    pub start_signal: String,
+    pub end_signal: String,
+    pub states: Vec<TargetState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_speed: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflicts: Option<Vec<DrivewayRef>>,
+}
+
+/// The state a driveway needs a track element in
+#[derive(Deserialize, Serialize, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TargetState {
+    pub element: String,
+    pub state: ElementState,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum ElementState {
+    Point(PointState),
+    VacancySection(VacancySectionState),
+    Signal(SignalState),
+}
+
+/// The state of signals that are not part of a target state
+fn stop() -> SignalState {
+    SignalState {
+        main: MainSignalState("hp0".to_string()),
+        additional: None,
+        zs3: None,
+        zs3v: None,
+    }
+}
+
+/// Looks up elements in the catalog while resolving one driveway
+struct Resolver<'a> {
+    catalog: &'a HashMap<&'a str, &'a CatalogElement>,
+    driveway: String,
+    /// The index of the driveway
+    index: usize,
+}
+
+impl Resolver<'_> {
+    /// `path` is the value inside the driveway that refers to the element
+    fn element(&self, id: &str, path: &str) -> Result<&CatalogElement, GenerationError> {
+        self.catalog
+            .get(id)
+            .copied()
+            .ok_or_else(|| GenerationError::UnknownTrackElement {
+                driveway: self.driveway.clone(),
+                element: id.to_string(),
+                location: Location::new(self.index, path),
+            })
+    }
+
+    fn signal(
+        &self,
+        id: &str,
+        state: SignalState,
+        path: &str,
+    ) -> Result<TrackElement, GenerationError> {
+        match self.element(id, path)? {
+            CatalogElement::Signal {
+                uuid,
+                name,
+                supported_states,
+            } => Ok(TrackElement::Signal {
+                uuid: uuid.clone(),
+                name: name.clone(),
+                supported_states: supported_states.clone(),
+                state,
+            }),
+            _ => Err(GenerationError::NotASignal {
+                driveway: self.driveway.clone(),
+                element: id.to_string(),
+                location: Location::new(self.index, path),
+            }),
+        }
+    }
+
+    fn target_state(
+        &self,
+        target_state: &TargetState,
+        path: &str,
+    ) -> Result<TrackElement, GenerationError> {
+        let TargetState { element, state } = target_state;
+        match (self.element(element, path)?, state) {
+            (CatalogElement::Point { uuid }, ElementState::Point(state)) => {
+                Ok(TrackElement::Point {
+                    uuid: uuid.clone(),
+                    state: state.clone(),
+                })
+            }
+            (CatalogElement::Signal { .. }, ElementState::Signal(state)) => {
+                self.signal(element, state.clone(), path)
+            }
+            (
+                CatalogElement::VacancySection {
+                    uuid,
+                    previous_signals,
+                },
+                ElementState::VacancySection(state),
+            ) => Ok(TrackElement::VacancySection {
+                uuid: uuid.clone(),
+                state: state.clone(),
+                previous_signals: previous_signals
+                    .iter()
+                    .map(|signal| self.signal(signal, stop(), path))
+                    .collect::<Result<_, _>>()?,
+            }),
+            _ => Err(GenerationError::MismatchedTargetState {
+                driveway: self.driveway.clone(),
+                element: element.clone(),
+                location: Location::new(self.index, path),
+            }),
+        }
+    }
+
+    fn driveway(&self, driveway: &NormalizedDriveway) -> Result<DrivewayRepr, GenerationError> {
+        let states = driveway
+            .states
+            .iter()
+            .enumerate()
+            .map(|(j, state)| self.target_state(state, &format!("/states/{j}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        // The start and end signal are in their target state if the driveway has one for them
+        let state_of = |signal: &str| {
+            states
+                .iter()
+                .find_map(|element| match element {
+                    TrackElement::Signal { uuid, state, .. } if uuid == signal => {
+                        Some(state.clone())
+                    }
+                    _ => None,
+                })
+                .unwrap_or_else(stop)
+        };
+
+        Ok(DrivewayRepr {
+            start_signal: self.signal(
+                &driveway.start_signal,
+                state_of(&driveway.start_signal),
+                "/start_signal",
+            )?,
+            end_signal: self.signal(
+                &driveway.end_signal,
+                state_of(&driveway.end_signal),
+                "/end_signal",
+            )?,
+            states,
+            max_speed: driveway.max_speed,
+            conflicts: driveway.conflicts.clone(),
+        })
+    }
+}
+
+impl TryFrom<NormalizedInput> for InterlockingRepr {
+    type Error = GenerationError;
+
+    fn try_from(input: NormalizedInput) -> Result<Self, Self::Error> {
+        if input.version != NORMALIZED_VERSION {
+            return Err(GenerationError::UnsupportedInputVersion(input.version));
+        }
+
+        let catalog: HashMap<_, _> = input
+            .elements
+            .iter()
+            .map(|element| (element.id(), element))
+            .collect();
+        let driveways = input
+            .driveways
+            .iter()
+            .enumerate()
+            .map(|(index, driveway)| {
+                let resolver = Resolver {
+                    catalog: &catalog,
+                    driveway: format!("{} - {}", driveway.start_signal, driveway.end_signal),
+                    index,
+                };
+                resolver.driveway(driveway)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(InterlockingRepr {
+            driveways,
+            conflicts: input.conflicts,
+            conflict_mode: input.conflict_mode,
+            derive_aspects: input.derive_aspects,
+            initial_state: input.initial_state,
+        })
+    }
+}
+
+fn catalog_element(element: TrackElement) -> CatalogElement {
+    match element {
+        TrackElement::Point { uuid, .. } => CatalogElement::Point { uuid },
+        TrackElement::Signal {
+            uuid,
+            name,
+            supported_states,
+            ..
+        } => CatalogElement::Signal {
+            uuid,
+            name,
+            supported_states,
+        },
+        TrackElement::VacancySection {
+            uuid,
+            previous_signals,
+            ..
+        } => CatalogElement::VacancySection {
+            uuid,
+            previous_signals: previous_signals
+                .iter()
+                .map(|signal| signal.id().to_string())
+                .collect(),
+        },
+    }
+}
+
+fn target_state(element: &TrackElement) -> TargetState {
+    let state = match element {
+        TrackElement::Point { state, .. } => ElementState::Point(state.clone()),
+        TrackElement::Signal { state, .. } => ElementState::Signal(state.clone()),
+        TrackElement::VacancySection { state, .. } => ElementState::VacancySection(state.clone()),
+    };
+    TargetState {
+        element: element.id().to_string(),
+        state,
+    }
+}
+
+/// Whether two signal states are the same, however their aspects are spelled. No additional
+/// signal state is the same as `off`.
+fn same_state(state: &SignalState, other: &SignalState) -> bool {
+    let main = |state: &SignalState| -> Option<Main> { (&state.main).try_into().ok() };
+    let additional = |state: &SignalState| -> Option<Additional> {
+        match &state.additional {
+            Some(additional) => additional.try_into().ok(),
+            None => Some(Additional::Off),
+        }
+    };
+    main(state) == main(other)
+        && additional(state) == additional(other)
+        && state.zs3 == other.zs3
+        && state.zs3v == other.zs3v
+}
+
+/// Check that the start or end signal of a driveway has the state that the normalized format
+/// gives it: its target state, or stop if the driveway has none for it
+fn check_signal_state(
+    driveway: &DrivewayRepr,
+    signal: &TrackElement,
+    location: Location,
+) -> Result<(), GenerationError> {
+    let TrackElement::Signal { uuid, state, .. } = signal else {
+        return Ok(());
+    };
+    let target_state = driveway
+        .states
+        .iter()
+        .find_map(|element| match element {
+            TrackElement::Signal {
+                uuid: id, state, ..
+            } if id == uuid => Some(state.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(stop);
+    if same_state(state, &target_state) {
+        Ok(())
+    } else {
+        Err(GenerationError::UnconvertibleSignalState {
+            driveway: driveway.name(),
+            signal: uuid.clone(),
+            location,
+        })
+    }
+}
+
+/// Convert the input into the normalized format. All descriptions of an element must agree,
+/// since the catalog contains only one of them, and the start and end signal of every
+/// driveway must be in their target state.
+pub fn normalize(interlocking: &InterlockingRepr) -> Result<NormalizedInput, GenerationError> {
+    let elements = collect_track_elements(&interlocking.driveways)?
+        .into_values()
+        .map(catalog_element)
+        .collect();
+    let driveways = interlocking
+        .driveways
+        .iter()
+        .enumerate()
+        .map(|(i, driveway)| {
+            check_signal_state(
+                driveway,
+                &driveway.start_signal,
+                Location::new(i, "/start_signal"),
+            )?;
+            check_signal_state(
+                driveway,
+                &driveway.end_signal,
+                Location::new(i, "/end_signal"),
+            )?;
+            Ok(NormalizedDriveway {
+                start_signal: driveway.start_signal.id().to_string(),
+                end_signal: driveway.end_signal.id().to_string(),
+                states: driveway.states.iter().map(target_state).collect(),
+                max_speed: driveway.max_speed,
+                conflicts: driveway.conflicts.clone(),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(NormalizedInput {
+        version: NORMALIZED_VERSION,
+        elements,
+        driveways,
+        conflicts: interlocking.conflicts.clone(),
+        conflict_mode: interlocking.conflict_mode,
+        derive_aspects: interlocking.derive_aspects,
+        initial_state: interlocking.initial_state.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn normalized_input(elements: Value) -> NormalizedInput {
+        serde_json::from_value(json!({
+            "version": 1,
+            "elements": elements,
+            "driveways": [
+                {
+                    "start_signal": "A",
+                    "end_signal": "C",
+                    "states": [
+                        {"element": "W1", "state": "right"},
+                        {"element": "T1", "state": "free"},
+                        {"element": "A", "state": {"main": "ks1", "zs3": 6}},
+                    ],
+                    "max_speed": 65,
+                },
+                {
+                    "start_signal": "C",
+                    "end_signal": "D",
+                    "states": [{"element": "W1", "state": "left"}],
+                    "conflicts": [{"start_signal": "A", "end_signal": "C"}],
+                },
+            ],
+            "conflict_mode": "replace",
+            "initial_state": {"points": {"W1": "right"}},
+        }))
+        .unwrap()
+    }
+
+    fn catalog() -> Value {
+        let signal = |id| {
+            json!({
+                "type": "signal",
+                "uuid": id,
+                "supported_states": {"main": ["hp0", "ks1"], "zs3": [6]},
+            })
+        };
+        // In the order of their IDs, like `normalize` writes them
+        json!([
+            signal("A"),
+            {"type": "signal", "uuid": "C", "name": "N1", "supported_states": {"main": ["hp0"]}},
+            signal("D"),
+            {"type": "vacancy_section", "uuid": "T1", "previous_signals": ["A"]},
+            {"type": "point", "uuid": "W1"},
+        ])
+    }
+
+    #[test]
+    fn normalized_input_round_trip() {
+        let input = normalized_input(catalog());
+        let interlocking = InterlockingRepr::try_from(normalized_input(catalog())).unwrap();
+        let normalized = normalize(&interlocking).unwrap();
+        assert_eq!(normalized, input);
+
+        let text = serde_json::to_string(&normalized).unwrap();
+        assert_eq!(
+            serde_json::from_str::<NormalizedInput>(&text).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn interlocking_round_trip() {
+        let interlocking = InterlockingRepr::try_from(normalized_input(catalog())).unwrap();
+        let TrackElement::Signal { state, .. } = &interlocking.driveways[0].start_signal else {
+            panic!("the start signal is not a signal");
+        };
+        assert_eq!(state.main, MainSignalState("ks1".to_string()));
+
+        let normalized = normalize(&interlocking).unwrap();
+        assert_eq!(
+            InterlockingRepr::try_from(normalized).unwrap(),
+            interlocking
+        );
+    }
+
+    #[test]
+    fn rejects_signal_states_that_are_not_target_states() {
+        let signal = |id: &str, main: &str| {
+            json!({
+                "type": "signal",
+                "uuid": id,
+                "supported_states": {"main": ["hp0", "ks1"]},
+                "state": {"main": main},
+            })
+        };
+        let interlocking = |start: &str| -> InterlockingRepr {
+            serde_json::from_value(json!([{
+                "start_signal": signal("A", start),
+                "end_signal": signal("C", "Hp0"),
+                "states": [{"type": "point", "uuid": "W1", "state": "left"}],
+            }]))
+            .unwrap()
+        };
+
+        assert!(normalize(&interlocking("HP0")).is_ok());
+        assert!(matches!(
+            normalize(&interlocking("ks1")),
+            Err(GenerationError::UnconvertibleSignalState { signal, location, .. })
+                if signal == "A" && location == Location::new(0, "/start_signal")
+        ));
+    }
+
+    #[test]
+    fn rejects_unresolvable_references() {
+        let mut elements = catalog();
+        elements.as_array_mut().unwrap().pop();
+        assert!(matches!(
+            InterlockingRepr::try_from(normalized_input(elements)),
+            Err(GenerationError::UnknownTrackElement { element, .. }) if element == "W1"
+        ));
+
+        let mut elements = catalog();
+        elements[4] = json!({"type": "vacancy_section", "uuid": "W1"});
+        assert!(matches!(
+            InterlockingRepr::try_from(normalized_input(elements)),
+            Err(GenerationError::MismatchedTargetState { element, .. }) if element == "W1"
+        ));
+
+        let mut input = normalized_input(catalog());
+        input.version = 2;
+        assert!(matches!(
+            InterlockingRepr::try_from(input),
+            Err(GenerationError::UnsupportedInputVersion(2))
+        ));
+    }
+}
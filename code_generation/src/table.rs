@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+
+use crate::{
+    conflicts::compute_conflicting_driveways,
+    driveway::{DrivewayRepr, InterlockingRepr, PointState, TrackElement},
+    generate::GenerationError,
+};
+
+const HEADER: [&str; 6] = [
+    "Start signal",
+    "End signal",
+    "Points",
+    "Vacancy sections",
+    "Signal aspects",
+    "Conflicts",
+];
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum TableFormat {
+    #[default]
+    Csv,
+    Html,
+}
+
+/// One row of the interlocking table, i.e. one driveway
+#[derive(Debug)]
+pub struct TableRow {
+    pub start_signal: String,
+    pub end_signal: String,
+    pub points: String,
+    pub vacancy_sections: String,
+    pub aspects: String,
+    pub conflicts: String,
+}
+
+impl TableRow {
+    fn cells(&self) -> [&str; 6] {
+        [
+            &self.start_signal,
+            &self.end_signal,
+            &self.points,
+            &self.vacancy_sections,
+            &self.aspects,
+            &self.conflicts,
+        ]
+    }
+}
+
+/// How a driveway is referred to in the table
+pub fn driveway_name(driveway: &DrivewayRepr) -> String {
+    format!(
+        "{} - {}",
+        driveway.start_signal.display_name(),
+        driveway.end_signal.display_name()
+    )
+}
+
+pub fn point_state_name(state: &PointState) -> &'static str {
+    match state {
+        PointState::Left => "left",
+        PointState::Right => "right",
+    }
+}
+
+/// The interlocking table (Verschlusstabelle) with one row per driveway
+pub fn interlocking_table(
+    interlocking: &InterlockingRepr,
+) -> Result<Vec<TableRow>, GenerationError> {
+    let conflicting_driveways = compute_conflicting_driveways(interlocking)?;
+    let names: HashMap<_, _> = interlocking
+        .driveways
+        .iter()
+        .map(|driveway| (driveway.id(), driveway_name(driveway)))
+        .collect();
+
+    let rows = interlocking
+        .driveways
+        .iter()
+        .map(|driveway| {
+            let mut points = vec![];
+            let mut vacancy_sections = vec![];
+            let mut aspects = vec![];
+            for element in &driveway.states {
+                match element {
+                    TrackElement::Point { uuid, state } => {
+                        points.push(format!("{}: {}", list_entry(uuid), point_state_name(state)));
+                    }
+                    TrackElement::VacancySection { uuid, .. } => {
+                        vacancy_sections.push(list_entry(uuid));
+                    }
+                    TrackElement::Signal { state, .. } => {
+                        let mut aspect =
+                            format!("{}: {}", list_entry(element.display_name()), state.main.0);
+                        if let Some(additional) = &state.additional {
+                            aspect.push_str(&format!(" {}", additional.0));
+                        }
+                        if let Some(zs3) = &state.zs3 {
+                            aspect.push_str(&format!(" Zs3 {zs3}"));
+                        }
+                        if let Some(zs3v) = &state.zs3v {
+                            aspect.push_str(&format!(" Zs3v {zs3v}"));
+                        }
+                        aspects.push(aspect);
+                    }
+                }
+            }
+
+            let conflicts: Vec<_> = conflicting_driveways[&driveway.id()]
+                .iter()
+                .map(|id| list_entry(&names[id]))
+                .collect();
+
+            TableRow {
+                start_signal: driveway.start_signal.display_name().to_string(),
+                end_signal: driveway.end_signal.display_name().to_string(),
+                points: points.join(", "),
+                vacancy_sections: vacancy_sections.join(", "),
+                aspects: aspects.join(", "),
+                conflicts: conflicts.join(", "),
+            }
+        })
+        .collect();
+    Ok(rows)
+}
+
+pub fn render(rows: &[TableRow], format: TableFormat) -> String {
+    match format {
+        TableFormat::Csv => render_csv(rows),
+        TableFormat::Html => render_html(rows),
+    }
+}
+
+/// Quote text that contains one of the given characters, the way CSV quotes fields
+fn quote(text: &str, special: &[char]) -> String {
+    if text.contains(special) || text.contains('"') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn csv_cell(cell: &str) -> String {
+    quote(cell, &[',', '\n'])
+}
+
+/// An entry of a list in a cell. Entries that contain a separator of the list or of a
+/// `point: position` pair are quoted, so that the `compare` subcommand can read them back.
+fn list_entry(entry: &str) -> String {
+    quote(entry, &[',', ':'])
+}
+
+fn render_csv(rows: &[TableRow]) -> String {
+    let mut csv = HEADER.map(csv_cell).join(",");
+    csv.push('\n');
+    for row in rows {
+        csv.push_str(&row.cells().map(csv_cell).join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(rows: &[TableRow]) -> String {
+    let header: String = HEADER
+        .iter()
+        .map(|cell| format!("<th>{}</th>", html_escape(cell)))
+        .collect();
+    let body: String = rows
+        .iter()
+        .map(|row| {
+            let cells: String = row
+                .cells()
+                .iter()
+                .map(|cell| format!("<td>{}</td>", html_escape(cell)))
+                .collect();
+            format!("      <tr>{cells}</tr>\n")
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>Interlocking table</title>
+    <style>
+      table {{ border-collapse: collapse; font-family: sans-serif; }}
+      th, td {{ border: 1px solid #999; padding: 4px 8px; text-align: left; }}
+      th {{ background: #eee; }}
+    </style>
+  </head>
+  <body>
+    <h1>Interlocking table</h1>
+    <table>
+      <tr>{header}</tr>
+{body}    </table>
+  </body>
+</html>
+"#
+    )
+}
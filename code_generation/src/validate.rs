@@ -0,0 +1,558 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::OnceLock,
+};
+
+use jsonschema::JSONSchema;
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    driveway::{
+        AdditionalSignalState, DrivewayRef, DrivewayRepr, InitialState, InterlockingObject,
+        InterlockingRepr, MainSignalState, SignalState, SupportedSignalStates, TrackElement,
+    },
+    normalized::{CatalogElement, ElementState, NormalizedInput, NORMALIZED_VERSION},
+};
+
+/// An error in the input, with the JSON pointer of the value it refers to
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// The JSON Schema of the generator input
+pub fn input_schema() -> RootSchema {
+    schema_for!(InterlockingRepr)
+}
+
+/// Escape a key for use in a JSON pointer
+fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Track elements are internally tagged enums, whose schema is a `oneOf` of the variants. A
+/// value that matches no variant only gets a single error for the whole value, so select the
+/// variant by its `type` tag instead. Then errors are reported for the values inside.
+fn select_tagged_variants(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            let tags = match object.get("oneOf") {
+                Some(Value::Array(variants)) => variants
+                    .iter()
+                    .map(|variant| match variant.pointer("/properties/type/enum") {
+                        Some(Value::Array(tag)) if tag.len() == 1 => Some(tag[0].clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>(),
+                _ => None,
+            };
+            if let Some(tags) = tags {
+                let Some(Value::Array(variants)) = object.remove("oneOf") else {
+                    unreachable!("the variants were found above");
+                };
+                let mut all_of = vec![json!({
+                    "required": ["type"],
+                    "properties": {"type": {"enum": tags}}
+                })];
+                for (tag, variant) in tags.into_iter().zip(variants) {
+                    all_of.push(json!({
+                        "if": {"required": ["type"], "properties": {"type": {"const": tag}}},
+                        "then": variant
+                    }));
+                }
+                object.insert("allOf".to_string(), Value::Array(all_of));
+            }
+
+            for value in object.values_mut() {
+                select_tagged_variants(value);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(select_tagged_variants),
+        _ => {}
+    }
+}
+
+/// Remove the patterns of signal aspects and additional signal states. `Checker` reports
+/// unknown spellings with a clearer message than the pattern, together with the other errors
+/// it finds.
+fn remove_patterns(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            object.remove("pattern");
+            object.values_mut().for_each(remove_patterns);
+        }
+        Value::Array(values) => values.iter_mut().for_each(remove_patterns),
+        _ => {}
+    }
+}
+
+// The compiled schemas of the input formats, which are only compiled once
+static DRIVEWAYS_SCHEMA: OnceLock<JSONSchema> = OnceLock::new();
+static NORMALIZED_SCHEMA: OnceLock<JSONSchema> = OnceLock::new();
+static OBJECT_SCHEMA: OnceLock<JSONSchema> = OnceLock::new();
+
+/// Check the input against the schema of one of the input formats and deserialize it. `schema`
+/// holds the compiled schema of `T`.
+fn parse<T: JsonSchema + DeserializeOwned>(
+    input: Value,
+    schema: &'static OnceLock<JSONSchema>,
+) -> Result<T, Vec<ValidationError>> {
+    let schema = schema.get_or_init(|| {
+        let mut schema =
+            serde_json::to_value(schema_for!(T)).expect("The input schema should be serializable");
+        select_tagged_variants(&mut schema);
+        remove_patterns(&mut schema);
+        JSONSchema::compile(&schema).expect("The input schema should be valid")
+    });
+    if let Err(errors) = schema.validate(&input) {
+        return Err(errors
+            .map(|error| ValidationError {
+                path: error.instance_path.to_string(),
+                message: error.to_string(),
+            })
+            .collect());
+    }
+
+    serde_json::from_value(input).map_err(|e| {
+        vec![ValidationError {
+            path: String::new(),
+            message: e.to_string(),
+        }]
+    })
+}
+
+/// Collects the semantic errors of the input
+#[derive(Default)]
+struct Checker {
+    errors: Vec<ValidationError>,
+    /// The type of every track element
+    elements: HashMap<String, &'static str>,
+    driveways: HashSet<String>,
+}
+
+impl Checker {
+    fn error(&mut self, path: String, message: String) {
+        self.errors.push(ValidationError { path, message });
+    }
+
+    fn main_aspect(&mut self, path: String, main: &MainSignalState) {
+        let aspect: Result<track_element::signal::MainSignalState, _> = main.try_into();
+        if aspect.is_err() {
+            self.error(path, format!("unknown main signal aspect `{}`", main.0));
+        }
+    }
+
+    fn additional(&mut self, path: String, additional: &AdditionalSignalState) {
+        let state: Result<track_element::signal::AdditionalSignalState, _> = additional.try_into();
+        if state.is_err() {
+            self.error(
+                path,
+                format!("unknown additional signal state `{}`", additional.0),
+            );
+        }
+    }
+
+    fn signal_state(&mut self, path: &str, state: &SignalState) {
+        self.main_aspect(format!("{path}/main"), &state.main);
+        if let Some(additional) = &state.additional {
+            self.additional(format!("{path}/additional"), additional);
+        }
+    }
+
+    fn supported_states(&mut self, path: &str, supported_states: &SupportedSignalStates) {
+        for (i, main) in supported_states.main.iter().enumerate() {
+            self.main_aspect(format!("{path}/main/{i}"), main);
+        }
+        for (i, additional) in supported_states.additional.iter().flatten().enumerate() {
+            self.additional(format!("{path}/additional/{i}"), additional);
+        }
+    }
+
+    fn element_type(&mut self, id: &str, element_type: &'static str) {
+        self.elements.insert(id.to_string(), element_type);
+    }
+
+    /// Check a track element of the formats that describe elements in every driveway
+    fn track_element(&mut self, path: &str, element: &TrackElement) {
+        match element {
+            TrackElement::Point { uuid, .. } => self.element_type(uuid, "point"),
+            TrackElement::Signal {
+                uuid,
+                supported_states,
+                state,
+                ..
+            } => {
+                self.element_type(uuid, "signal");
+                self.supported_states(&format!("{path}/supported_states"), supported_states);
+                self.signal_state(&format!("{path}/state"), state);
+            }
+            TrackElement::VacancySection {
+                uuid,
+                previous_signals,
+                ..
+            } => {
+                self.element_type(uuid, "vacancy section");
+                for (i, signal) in previous_signals.iter().enumerate() {
+                    self.track_element(&format!("{path}/previous_signals/{i}"), signal);
+                }
+            }
+        }
+    }
+
+    fn reference(&mut self, path: String, id: &str, element_type: &'static str) {
+        match self.elements.get(id) {
+            None => self.error(path, format!("unknown track element `{id}`")),
+            Some(actual) if *actual != element_type => {
+                let actual = *actual;
+                self.error(path, format!("`{id}` is a {actual}, not a {element_type}"));
+            }
+            _ => {}
+        }
+    }
+
+    fn driveway_reference(&mut self, path: String, driveway: &DrivewayRef) {
+        if !self.driveways.contains(&driveway.id()) {
+            self.error(
+                path,
+                format!(
+                    "unknown driveway {} - {}",
+                    driveway.start_signal, driveway.end_signal
+                ),
+            );
+        }
+    }
+
+    fn driveway_conflicts(&mut self, path: &str, conflicts: &Option<Vec<DrivewayRef>>) {
+        for (i, conflict) in conflicts.iter().flatten().enumerate() {
+            self.driveway_reference(format!("{path}/conflicts/{i}"), conflict);
+        }
+    }
+
+    fn conflicts(&mut self, conflicts: &[[DrivewayRef; 2]]) {
+        for (i, pair) in conflicts.iter().enumerate() {
+            for (j, driveway) in pair.iter().enumerate() {
+                self.driveway_reference(format!("/conflicts/{i}/{j}"), driveway);
+            }
+        }
+    }
+
+    fn initial_state(&mut self, initial_state: &InitialState) {
+        for id in initial_state.points.keys() {
+            let path = format!("/initial_state/points/{}", pointer_token(id));
+            self.reference(path, id, "point");
+        }
+        for (id, state) in &initial_state.signals {
+            let path = format!("/initial_state/signals/{}", pointer_token(id));
+            self.reference(path.clone(), id, "signal");
+            self.signal_state(&path, state);
+        }
+        for id in initial_state.vacancy_sections.keys() {
+            let path = format!("/initial_state/vacancy_sections/{}", pointer_token(id));
+            self.reference(path, id, "vacancy section");
+        }
+    }
+
+    /// Check driveways that describe their track elements in full. `path` is the JSON
+    /// pointer of the list of driveways.
+    fn driveways(&mut self, path: &str, driveways: &[DrivewayRepr]) {
+        for driveway in driveways {
+            self.driveways.insert(driveway.id());
+        }
+        for (i, driveway) in driveways.iter().enumerate() {
+            let path = format!("{path}/{i}");
+            self.track_element(&format!("{path}/start_signal"), &driveway.start_signal);
+            self.track_element(&format!("{path}/end_signal"), &driveway.end_signal);
+            for (j, element) in driveway.states.iter().enumerate() {
+                self.track_element(&format!("{path}/states/{j}"), element);
+            }
+            self.driveway_conflicts(&path, &driveway.conflicts);
+        }
+    }
+
+    fn normalized(&mut self, input: &NormalizedInput) {
+        if input.version != NORMALIZED_VERSION {
+            self.error(
+                "/version".to_string(),
+                format!(
+                    "version {} is not supported, expected version {NORMALIZED_VERSION}",
+                    input.version
+                ),
+            );
+        }
+        for (i, element) in input.elements.iter().enumerate() {
+            let path = format!("/elements/{i}");
+            match element {
+                CatalogElement::Point { uuid } => self.element_type(uuid, "point"),
+                CatalogElement::Signal {
+                    uuid,
+                    supported_states,
+                    ..
+                } => {
+                    self.element_type(uuid, "signal");
+                    self.supported_states(&format!("{path}/supported_states"), supported_states);
+                }
+                CatalogElement::VacancySection { uuid, .. } => {
+                    self.element_type(uuid, "vacancy section")
+                }
+            }
+        }
+        for (i, element) in input.elements.iter().enumerate() {
+            if let CatalogElement::VacancySection {
+                previous_signals, ..
+            } = element
+            {
+                for (j, signal) in previous_signals.iter().enumerate() {
+                    self.reference(
+                        format!("/elements/{i}/previous_signals/{j}"),
+                        signal,
+                        "signal",
+                    );
+                }
+            }
+        }
+
+        for driveway in &input.driveways {
+            self.driveways.insert(
+                DrivewayRef {
+                    start_signal: driveway.start_signal.clone(),
+                    end_signal: driveway.end_signal.clone(),
+                }
+                .id(),
+            );
+        }
+        for (i, driveway) in input.driveways.iter().enumerate() {
+            let path = format!("/driveways/{i}");
+            self.reference(
+                format!("{path}/start_signal"),
+                &driveway.start_signal,
+                "signal",
+            );
+            self.reference(format!("{path}/end_signal"), &driveway.end_signal, "signal");
+            for (j, target_state) in driveway.states.iter().enumerate() {
+                let path = format!("{path}/states/{j}");
+                let element_type = match &target_state.state {
+                    ElementState::Point(_) => "point",
+                    ElementState::VacancySection(_) => "vacancy section",
+                    ElementState::Signal(state) => {
+                        self.signal_state(&format!("{path}/state"), state);
+                        "signal"
+                    }
+                };
+                self.reference(
+                    format!("{path}/element"),
+                    &target_state.element,
+                    element_type,
+                );
+            }
+            self.driveway_conflicts(&path, &driveway.conflicts);
+        }
+    }
+}
+
+/// Check the input against the JSON Schema of its format and against the rules the schema
+/// cannot express: known signal aspects and references to existing elements and driveways.
+/// All errors are returned, each with the JSON pointer of the value it refers to.
+pub fn validate(input: &str) -> Vec<ValidationError> {
+    let input: Value = match serde_json::from_str(input) {
+        Ok(input) => input,
+        Err(e) => {
+            return vec![ValidationError {
+                path: String::new(),
+                message: e.to_string(),
+            }]
+        }
+    };
+
+    let mut checker = Checker::default();
+    let result = match input {
+        Value::Array(_) => parse::<Vec<DrivewayRepr>>(input, &DRIVEWAYS_SCHEMA).map(|driveways| {
+            checker.driveways("", &driveways);
+        }),
+        Value::Object(ref object) if object.contains_key("version") => {
+            parse::<NormalizedInput>(input, &NORMALIZED_SCHEMA).map(|input| {
+                checker.normalized(&input);
+                checker.conflicts(&input.conflicts);
+                checker.initial_state(&input.initial_state);
+            })
+        }
+        _ => parse::<InterlockingObject>(input, &OBJECT_SCHEMA).map(|input| {
+            checker.driveways("/driveways", &input.driveways);
+            checker.conflicts(&input.conflicts);
+            checker.initial_state(&input.initial_state);
+        }),
+    };
+
+    match result {
+        Ok(()) => checker.errors,
+        Err(errors) => errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(id: &str, main: &str) -> Value {
+        json!({
+            "type": "signal",
+            "uuid": id,
+            "supported_states": {"main": ["hp0", "ks1"]},
+            "state": {"main": main},
+        })
+    }
+
+    fn driveways(main: &str) -> Value {
+        json!([{
+            "start_signal": signal("A", "hp0"),
+            "end_signal": signal("C", "hp0"),
+            "states": [signal("A", main), {"type": "point", "uuid": "W1", "state": "left"}],
+        }])
+    }
+
+    fn paths(input: &Value) -> Vec<String> {
+        let mut paths: Vec<_> = validate(&input.to_string())
+            .into_iter()
+            .map(|error| error.path)
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn published_schema_is_up_to_date() {
+        let published: Value = serde_json::from_str(include_str!("../input.schema.json")).unwrap();
+        assert_eq!(
+            published,
+            serde_json::to_value(input_schema()).unwrap(),
+            "input.schema.json is outdated, write it again with the `schema` subcommand"
+        );
+    }
+
+    #[test]
+    fn accepts_valid_input() {
+        assert_eq!(validate(&driveways("ks1").to_string()), vec![]);
+    }
+
+    #[test]
+    fn accepts_every_spelling_of_an_aspect() {
+        for main in [
+            "Ks1",
+            "KS_1",
+            "hp0+sh1",
+            "Hp0 Plus Sh1",
+            "hp0-sh1",
+            "Kennlicht",
+        ] {
+            let mut input = driveways(main);
+            input[0]["states"][0]["supported_states"]["main"] = json!([main]);
+            input[0]["start_signal"]["supported_states"]["main"] = json!([main]);
+            assert_eq!(validate(&input.to_string()), vec![], "{main}");
+        }
+    }
+
+    #[test]
+    fn published_patterns_accept_every_spelling() {
+        let main =
+            JSONSchema::compile(&serde_json::to_value(schema_for!(MainSignalState)).unwrap())
+                .unwrap();
+        for spelling in ["Ks1", "KS_1", "hp0+sh1", "Hp0 Plus Sh1", "HP0-SH1", "dark"] {
+            assert!(main.is_valid(&json!(spelling)), "{spelling}");
+        }
+        for spelling in ["ks9", "ks1x", "+", ""] {
+            assert!(!main.is_valid(&json!(spelling)), "{spelling}");
+        }
+
+        let additional =
+            JSONSchema::compile(&serde_json::to_value(schema_for!(AdditionalSignalState)).unwrap())
+                .unwrap();
+        assert!(additional.is_valid(&json!("Zs13")));
+        assert!(!additional.is_valid(&json!("zs_13")));
+    }
+
+    #[test]
+    fn rejects_unknown_aspects() {
+        assert_eq!(
+            validate(&driveways("ks9").to_string()),
+            [ValidationError {
+                path: "/0/states/0/state/main".to_string(),
+                message: "unknown main signal aspect `ks9`".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_properties() {
+        let mut input = driveways("ks1");
+        input[0]["max-speed"] = json!(60);
+        input[0]["states"][1]["position"] = json!("left");
+        assert_eq!(paths(&input), ["/0", "/0/states/1"]);
+
+        let input = json!({
+            "driveways": driveways("ks1"),
+            "conflicts_mode": "replace",
+            "derive_aspect": true,
+        });
+        assert_eq!(validate(&input.to_string()).len(), 1);
+        assert!(validate(&input.to_string())[0]
+            .message
+            .contains("'conflicts_mode', 'derive_aspect' were unexpected"));
+    }
+
+    #[test]
+    fn reports_unknown_references() {
+        let input = json!({
+            "driveways": driveways("ks1"),
+            "conflicts": [[
+                {"start_signal": "A", "end_signal": "C"},
+                {"start_signal": "X", "end_signal": "Y"},
+            ]],
+            "initial_state": {"points": {"A": "left", "W2": "right"}},
+        });
+        assert_eq!(
+            paths(&input),
+            [
+                "/conflicts/0/1",
+                "/initial_state/points/A",
+                "/initial_state/points/W2"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_of_the_normalized_format() {
+        let input = json!({
+            "version": 1,
+            "elements": [
+                {"type": "signal", "uuid": "A", "supported_states": {"main": ["hp0", "ks9"]}},
+                {"type": "point", "uuid": "W1"},
+            ],
+            "driveways": [{
+                "start_signal": "A",
+                "end_signal": "W1",
+                "states": [{"element": "B", "state": "left"}],
+            }],
+        });
+        assert_eq!(
+            paths(&input),
+            [
+                "/driveways/0/end_signal",
+                "/driveways/0/states/0/element",
+                "/elements/0/supported_states/main/1"
+            ]
+        );
+    }
+}
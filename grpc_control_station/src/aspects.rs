@@ -0,0 +1,107 @@
+use track_element::{
+    signal::{AdditionalSignalZs3Symbol, MainSignalState, SignalState},
+    TrackElement,
+};
+
+use crate::{
+    lookup::{find_driveway, find_signal},
+    stop_aspect, InterlockingState,
+};
+
+/// A signal whose aspect depends on the aspect of the end signal of a driveway while that
+/// driveway is set, e.g. a start signal showing Ks1 or Ks2 or a distant signal showing Vr1
+/// or Vr0
+#[derive(Clone, Debug)]
+pub struct DerivedAspect {
+    start_signal: String,
+    end_signal: String,
+    signal: String,
+    /// The aspect while the end signal shows proceed
+    proceed: MainSignalState,
+    /// The aspect while the end signal shows stop
+    caution: MainSignalState,
+}
+
+impl DerivedAspect {
+    pub fn new<S: Into<String>>(
+        start_signal: S,
+        end_signal: S,
+        signal: S,
+        proceed: MainSignalState,
+        caution: MainSignalState,
+    ) -> Self {
+        Self {
+            start_signal: start_signal.into(),
+            end_signal: end_signal.into(),
+            signal: signal.into(),
+            proceed,
+            caution,
+        }
+    }
+}
+
+fn shows_stop(state: &SignalState) -> bool {
+    stop_aspect::is_stop(&state.main())
+}
+
+impl InterlockingState {
+    /// Show proceed or caution on the derived signals of all set driveways, depending on
+    /// the current aspect of their end signals. Since an end signal may itself be derived,
+    /// this is repeated until no aspect changes anymore.
+    pub(crate) fn update_derived_aspects(&self) {
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        for _ in 0..=self.derived_aspects.len() {
+            let mut changed = false;
+            for derived in self.derived_aspects.iter() {
+                let Some((_, driveway)) = find_driveway(
+                    &driveway_manager,
+                    &derived.start_signal,
+                    &derived.end_signal,
+                ) else {
+                    continue;
+                };
+                let driveway = driveway.read().unwrap();
+                if !driveway.is_set()
+                    || shows_stop(&driveway.start_signal().read().unwrap().state())
+                {
+                    continue;
+                }
+                let next_state = driveway.end_signal().read().unwrap().state();
+                let Some((signal, _)) = find_signal(&driveway_manager, &derived.signal) else {
+                    continue;
+                };
+
+                let mut signal = signal.write().unwrap();
+                let current = signal.state();
+                let state = if shows_stop(&next_state) {
+                    SignalState::new(
+                        derived.caution,
+                        current.additional(),
+                        current.zs3(),
+                        AdditionalSignalZs3Symbol::OFF,
+                    )
+                } else {
+                    SignalState::new(
+                        derived.proceed,
+                        current.additional(),
+                        current.zs3(),
+                        next_state.zs3(),
+                    )
+                };
+                if state == current {
+                    continue;
+                }
+                match signal.set_state(state) {
+                    Ok(()) => changed = true,
+                    Err(e) => println!(
+                        "Error updating the aspect of signal {} for driveway {} - {}: {e:?}",
+                        derived.signal, derived.start_signal, derived.end_signal
+                    ),
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use track_element::vacancy_section::VacancySectionState;
+
+/// An auxiliary action (Hilfshandlung) that was carried out on a vacancy section
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct AuxiliaryAction {
+    pub(crate) vacancy_section: String,
+    pub(crate) description: String,
+}
+
+/// Bookkeeping for axle counter resets. Every reset is an auxiliary action (Hilfshandlung)
+/// and is counted and logged.
+#[derive(Debug, Default)]
+pub(crate) struct AxleCounterResets {
+    /// Vacancy sections that were reset and are in the preparatory state, together with
+    /// whether a train has entered them since the reset
+    preparatory: BTreeMap<String, bool>,
+    /// Number of resets per vacancy section
+    counters: BTreeMap<String, u32>,
+    /// Every auxiliary action in the order they were carried out
+    log: Vec<AuxiliaryAction>,
+}
+
+impl AxleCounterResets {
+    pub(crate) fn is_preparatory(&self, vacancy_section: &str) -> bool {
+        self.preparatory.contains_key(vacancy_section)
+    }
+
+    /// Put a vacancy section into the preparatory state
+    pub(crate) fn reset(&mut self, vacancy_section: &str) {
+        self.preparatory.insert(vacancy_section.to_string(), false);
+        let counter = self
+            .counters
+            .entry(vacancy_section.to_string())
+            .or_insert(0);
+        *counter += 1;
+        let description = format!("axle counter reset (reset #{counter} of this section)");
+        self.record(vacancy_section, description);
+    }
+
+    /// Confirm that a vacancy section in the preparatory state is clear
+    pub(crate) fn confirm(&mut self, vacancy_section: &str) {
+        if self.preparatory.remove(vacancy_section).is_some() {
+            self.record(
+                vacancy_section,
+                "confirmed clear after axle counter reset".to_string(),
+            );
+        }
+    }
+
+    fn record(&mut self, vacancy_section: &str, description: String) {
+        println!(
+            "Hilfshandlung: vacancy section {vacancy_section}: {description} ({} axle counter resets in total)",
+            self.total()
+        );
+        self.log.push(AuxiliaryAction {
+            vacancy_section: vacancy_section.to_string(),
+            description,
+        });
+    }
+
+    /// Leave the preparatory state once a train has passed through the vacancy section
+    pub(crate) fn update(&mut self, vacancy_section: &str, state: &VacancySectionState) {
+        let Some(train_entered) = self.preparatory.get_mut(vacancy_section) else {
+            return;
+        };
+
+        match state {
+            VacancySectionState::Occupied => *train_entered = true,
+            VacancySectionState::Free if *train_entered => {
+                self.preparatory.remove(vacancy_section);
+                println!(
+                    "Vacancy section {vacancy_section} cleared by a train after axle counter reset"
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// The number of axle counter resets of a vacancy section
+    pub(crate) fn count(&self, vacancy_section: &str) -> u32 {
+        self.counters
+            .get(vacancy_section)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The number of axle counter resets of all vacancy sections
+    pub(crate) fn total(&self) -> u32 {
+        self.counters.values().sum()
+    }
+
+    pub(crate) fn log(&self) -> &[AuxiliaryAction] {
+        &self.log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::{Code, Request};
+    use track_element::vacancy_section::VacancySectionState;
+
+    use crate::{
+        element_state::element_state,
+        fixture::{move_point, request_route, set_vacancy_section, station},
+        ixl::{interlocking_server::Interlocking, MpDirection, RacCommand},
+        InterlockingState,
+    };
+
+    async fn reset(state: &InterlockingState, tps: &str) -> Result<(), tonic::Status> {
+        let command = RacCommand {
+            tps: tps.to_string(),
+        };
+        Interlocking::reset_axle_counter(state, Request::new(command))
+            .await
+            .map(|_| ())
+    }
+
+    async fn confirm(state: &InterlockingState, tps: &str) -> Result<(), tonic::Status> {
+        let command = RacCommand {
+            tps: tps.to_string(),
+        };
+        Interlocking::confirm_axle_counter_reset(state, Request::new(command))
+            .await
+            .map(|_| ())
+    }
+
+    fn is_preparatory(state: &InterlockingState, tps: &str) -> bool {
+        state
+            .axle_counter_resets
+            .read()
+            .unwrap()
+            .is_preparatory(tps)
+    }
+
+    #[tokio::test]
+    async fn reset_sections_are_not_clear_until_confirmed() {
+        let state = station().interlocking_state();
+        set_vacancy_section(&state, "T1", VacancySectionState::Disturbed);
+        reset(&state, "T1").await.unwrap();
+        assert!(is_preparatory(&state, "T1"));
+
+        let status = move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let status = request_route(&state, "A", "C").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.message().contains("reset"), "{}", status.message());
+
+        confirm(&state, "T1").await.unwrap();
+        assert!(!is_preparatory(&state, "T1"));
+        move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap();
+        request_route(&state, "B", "C").await.unwrap();
+
+        let details = element_state(&state, "T1").unwrap();
+        assert_eq!(details.state, "Free");
+        assert_eq!(details.axle_counter_resets, 1);
+        let log = state.axle_counter_resets.read().unwrap().log().to_vec();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|action| action.vacancy_section == "T1"));
+    }
+
+    #[tokio::test]
+    async fn a_passing_train_ends_the_preparatory_state() {
+        let state = station().interlocking_state();
+        set_vacancy_section(&state, "T2", VacancySectionState::CommunicationError);
+        reset(&state, "T2").await.unwrap();
+
+        state
+            .report_vacancy_section_state("T2", VacancySectionState::Occupied)
+            .unwrap();
+        assert!(is_preparatory(&state, "T2"));
+        state
+            .report_vacancy_section_state("T2", VacancySectionState::Free)
+            .unwrap();
+        assert!(!is_preparatory(&state, "T2"));
+        request_route(&state, "C", "D").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sections_of_set_driveways_cannot_be_reset() {
+        let state = station().interlocking_state();
+        request_route(&state, "C", "D").await.unwrap();
+        state
+            .report_vacancy_section_state("T2", VacancySectionState::Disturbed)
+            .unwrap();
+
+        let status = reset(&state, "T2").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(!is_preparatory(&state, "T2"));
+        assert_eq!(state.axle_counter_resets.read().unwrap().total(), 0);
+    }
+
+    #[tokio::test]
+    async fn only_disturbed_sections_can_be_reset() {
+        let state = station().interlocking_state();
+        let status = reset(&state, "T1").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let status = confirm(&state, "T1").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+use track_element::{vacancy_section::VacancySectionState, TrackElement};
+
+use crate::{
+    element_state::{
+        additional_signal_state_to_string, main_signal_state_to_string, zs3_to_number, TRAILED,
+    },
+    ixl::{
+        self, state_change::Change, DrivewayStateChange, PointStateChange, SignalStateChange,
+        VacancySectionStateChange,
+    },
+    point_state_to_string, signal_state_to_string, vacancy_section_state_to_string,
+    InterlockingState,
+};
+
+/// The state of an element or driveway as sent to subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ObservedState {
+    Point(&'static str),
+    Signal {
+        aspect: &'static str,
+        main: &'static str,
+        additional: &'static str,
+        zs3: u32,
+        zs3v: u32,
+    },
+    VacancySection(&'static str),
+    Driveway {
+        set: bool,
+    },
+}
+
+impl ObservedState {
+    /// The state as shown in the WebSocket feed. Driveways are not part of it.
+    pub(crate) fn ws_state(&self) -> Option<&'static str> {
+        match self {
+            ObservedState::Point(state) => Some(state),
+            ObservedState::Signal { aspect, .. } => Some(aspect),
+            ObservedState::VacancySection(state) => Some(state),
+            ObservedState::Driveway { .. } => None,
+        }
+    }
+}
+
+/// A new state of a single element or driveway
+#[derive(Clone, Debug)]
+pub(crate) struct StateUpdate {
+    pub(crate) id: String,
+    pub(crate) state: ObservedState,
+}
+
+/// A batch of state changes, numbered so subscribers can tell which state they have seen
+#[derive(Clone, Debug)]
+pub(crate) struct StateUpdates {
+    pub(crate) id: u64,
+    pub(crate) updates: Vec<StateUpdate>,
+    /// The operations that are valid after these changes, see `valid_transitions`
+    pub(crate) valid_transitions: Value,
+    pub(crate) pending_command: Option<PendingCommand>,
+}
+
+/// The states of all elements and driveways. Elements and driveways are kept apart, since
+/// a driveway may have the same ID as an element.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ObservedStates {
+    pub(crate) elements: BTreeMap<String, ObservedState>,
+    pub(crate) driveways: BTreeMap<String, ObservedState>,
+}
+
+impl ObservedStates {
+    /// Every state, elements first
+    pub(crate) fn updates(&self) -> Vec<StateUpdate> {
+        self.changes_since(&ObservedStates::default())
+    }
+
+    /// The states that differ from the ones in `previous`
+    pub(crate) fn changes_since(&self, previous: &ObservedStates) -> Vec<StateUpdate> {
+        let changes = |states: &BTreeMap<String, ObservedState>,
+                       previous: &BTreeMap<String, ObservedState>| {
+            states
+                .iter()
+                .filter(|(id, state)| previous.get(*id) != Some(state))
+                .map(|(id, state)| StateUpdate {
+                    id: id.clone(),
+                    state: state.clone(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut updates = changes(&self.elements, &previous.elements);
+        updates.extend(changes(&self.driveways, &previous.driveways));
+        updates
+    }
+}
+
+/// The states that were last sent to subscribers
+#[derive(Debug, Default)]
+pub(crate) struct PublishedStates {
+    pub(crate) id: u64,
+    pub(crate) states: ObservedStates,
+    pub(crate) valid_transitions: Value,
+    pub(crate) pending_command: Option<PendingCommand>,
+}
+
+/// A command that was accepted but has not taken effect yet
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PendingCommand {
+    /// A driveway whose approach section is not clear is released after the release delay
+    ReleaseRoute { driveway: String, start: String },
+}
+
+impl PendingCommand {
+    pub(crate) fn releases(&self, driveway_id: &str) -> bool {
+        match self {
+            PendingCommand::ReleaseRoute { driveway, .. } => driveway == driveway_id,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            PendingCommand::ReleaseRoute { driveway, start } => json!({
+                "type": "ReleaseRoute",
+                "driveway": driveway,
+                "start": start,
+            }),
+        }
+    }
+}
+
+impl From<&StateUpdate> for ixl::StateChange {
+    fn from(update: &StateUpdate) -> Self {
+        let id = update.id.clone();
+        let change = match &update.state {
+            ObservedState::Point(state) => Change::Point(PointStateChange {
+                point: id,
+                state: state.to_string(),
+            }),
+            ObservedState::Signal {
+                main,
+                additional,
+                zs3,
+                zs3v,
+                ..
+            } => Change::Signal(SignalStateChange {
+                signal: id,
+                main: main.to_string(),
+                additional: additional.to_string(),
+                zs3: *zs3,
+                zs3v: *zs3v,
+            }),
+            ObservedState::VacancySection(state) => {
+                Change::VacancySection(VacancySectionStateChange {
+                    vacancy_section: id,
+                    state: state.to_string(),
+                })
+            }
+            ObservedState::Driveway { set } => Change::Driveway(DrivewayStateChange {
+                driveway: id,
+                set: *set,
+            }),
+        };
+        ixl::StateChange {
+            change: Some(change),
+        }
+    }
+}
+
+/// The current state of every element and driveway
+pub(crate) fn observed_states(interlocking_state: &InterlockingState) -> ObservedStates {
+    let driveway_manager = interlocking_state.driveway_manager.read().unwrap();
+    let axle_counter_resets = interlocking_state.axle_counter_resets.read().unwrap();
+    let trailed_points = interlocking_state.trailed_points.read().unwrap();
+    let state = driveway_manager.state();
+
+    let mut states = ObservedStates::default();
+
+    for (point, state) in state.points() {
+        let point = point.read().unwrap();
+        let state = if trailed_points.contains(point.id()) {
+            TRAILED
+        } else {
+            point_state_to_string(state)
+        };
+        states
+            .elements
+            .insert(point.id().to_string(), ObservedState::Point(state));
+    }
+
+    for (signal, state) in state.signals() {
+        states.elements.insert(
+            signal.read().unwrap().id().to_string(),
+            ObservedState::Signal {
+                aspect: signal_state_to_string(state),
+                main: main_signal_state_to_string(&state.main()),
+                additional: additional_signal_state_to_string(&state.additional()),
+                zs3: zs3_to_number(&state.zs3()),
+                zs3v: zs3_to_number(&state.zs3v()),
+            },
+        );
+    }
+
+    for (vacancy_section, state) in state.vacancy_sections() {
+        let vacancy_section = vacancy_section.read().unwrap();
+        let state = if axle_counter_resets.is_preparatory(vacancy_section.id()) {
+            "Preparatory"
+        } else if !matches!(state, VacancySectionState::Free) {
+            vacancy_section_state_to_string(state)
+        } else if driveway_manager.is_allocated(&vacancy_section) {
+            "Allocated"
+        } else {
+            "Unallocated"
+        };
+        states.elements.insert(
+            vacancy_section.id().to_string(),
+            ObservedState::VacancySection(state),
+        );
+    }
+
+    for id in driveway_manager.get_driveway_ids() {
+        if let Some(driveway) = driveway_manager.get(id) {
+            states.driveways.insert(
+                id.to_string(),
+                ObservedState::Driveway {
+                    set: driveway.read().unwrap().is_set(),
+                },
+            );
+        }
+    }
+
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        driveway_state_to_json,
+        fixture::{request_route, station},
+    };
+
+    #[tokio::test]
+    async fn updates_carry_the_valid_transitions() {
+        let state = station().interlocking_state();
+        state.notify_changes();
+        let mut changes = state.changes.subscribe();
+
+        request_route(&state, "A", "C").await.unwrap();
+
+        let updates = changes.try_recv().unwrap();
+        assert_eq!(
+            updates.valid_transitions["requestRoute"],
+            json!([{ "start": "C", "ziel": "D" }])
+        );
+        assert_eq!(
+            updates.valid_transitions["releaseRoute"],
+            json!([{ "start": "A" }])
+        );
+        assert_eq!(
+            driveway_state_to_json(&updates)["validTransitions"],
+            updates.valid_transitions
+        );
+        assert_eq!(
+            state.snapshot().valid_transitions,
+            updates.valid_transitions
+        );
+    }
+
+    #[test]
+    fn elements_and_driveways_are_kept_apart() {
+        let mut previous = ObservedStates::default();
+        previous
+            .elements
+            .insert("X".to_string(), ObservedState::Point("Left"));
+        let mut states = ObservedStates::default();
+        states
+            .elements
+            .insert("X".to_string(), ObservedState::Point("Left"));
+        states
+            .driveways
+            .insert("X".to_string(), ObservedState::Driveway { set: true });
+
+        let changes = states.changes_since(&previous);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].state, ObservedState::Driveway { set: true });
+        assert_eq!(states.updates().len(), 2);
+    }
+}
@@ -0,0 +1,203 @@
+use track_element::{
+    signal::{AdditionalSignalState, AdditionalSignalZs3Symbol, MainSignalState},
+    vacancy_section::VacancySectionState,
+};
+
+use crate::{
+    ixl::{ElementStateDetails, ElementType},
+    lookup::{find_point, find_signal, find_vacancy_section, locking_driveway},
+    point_state_to_string, InterlockingState,
+};
+
+/// The state of a trailed point instead of its position
+pub(crate) const TRAILED: &str = "Trailed";
+
+/// The current state of a single point, signal or vacancy section
+pub(crate) fn element_state(state: &InterlockingState, id: &str) -> Option<ElementStateDetails> {
+    let driveway_manager = state.driveway_manager.read().unwrap();
+    let driveway = locking_driveway(&driveway_manager, id);
+
+    let mut details = ElementStateDetails {
+        element: id.to_string(),
+        locked: driveway.is_some(),
+        driveway: driveway.unwrap_or_default(),
+        ..Default::default()
+    };
+
+    if let Some((_, point_state)) = find_point(&driveway_manager, id) {
+        details.set_type(ElementType::ElementPoint);
+        details.state = if state.trailed_points.read().unwrap().contains(id) {
+            TRAILED.to_string()
+        } else {
+            point_state_to_string(&point_state).to_string()
+        };
+    } else if let Some((_, signal_state)) = find_signal(&driveway_manager, id) {
+        details.set_type(ElementType::ElementSignal);
+        details.state = main_signal_state_to_string(&signal_state.main()).to_string();
+        details.additional =
+            additional_signal_state_to_string(&signal_state.additional()).to_string();
+        details.zs3 = zs3_to_number(&signal_state.zs3());
+        details.zs3v = zs3_to_number(&signal_state.zs3v());
+    } else if let Some((_, vacancy_section_state)) = find_vacancy_section(&driveway_manager, id) {
+        details.set_type(ElementType::ElementVacancySection);
+        let axle_counter_resets = state.axle_counter_resets.read().unwrap();
+        details.state = if axle_counter_resets.is_preparatory(id) {
+            "Preparatory".to_string()
+        } else {
+            vacancy_section_state_name(&vacancy_section_state).to_string()
+        };
+        details.axle_counter_resets = axle_counter_resets.count(id);
+    } else {
+        return None;
+    }
+
+    Some(details)
+}
+
+pub(crate) fn element_type_name(element_type: ElementType) -> &'static str {
+    match element_type {
+        ElementType::Unspecified => "element",
+        ElementType::ElementPoint => "point",
+        ElementType::ElementSignal => "signal",
+        ElementType::ElementVacancySection => "vacancy section",
+    }
+}
+
+pub(crate) fn main_signal_state_to_string(state: &MainSignalState) -> &'static str {
+    match state {
+        MainSignalState::Hp0 => "Hp0",
+        MainSignalState::Hp0PlusSh1 => "Hp0PlusSh1",
+        MainSignalState::Hp0WithDrivingIndicator => "Hp0WithDrivingIndicator",
+        MainSignalState::Ks1 => "Ks1",
+        MainSignalState::Ks1Flashing => "Ks1Flashing",
+        MainSignalState::Ks1FlashingWithAdditionalLight => "Ks1FlashingWithAdditionalLight",
+        MainSignalState::Ks2 => "Ks2",
+        MainSignalState::Ks2WithAdditionalLight => "Ks2WithAdditionalLight",
+        MainSignalState::Sh1 => "Sh1",
+        MainSignalState::IdLight => "IdLight",
+        MainSignalState::Hp0Hv => "Hp0Hv",
+        MainSignalState::Hp1 => "Hp1",
+        MainSignalState::Hp2 => "Hp2",
+        MainSignalState::Vr0 => "Vr0",
+        MainSignalState::Vr1 => "Vr1",
+        MainSignalState::Vr2 => "Vr2",
+        MainSignalState::Off => "Off",
+    }
+}
+
+pub(crate) fn additional_signal_state_to_string(state: &AdditionalSignalState) -> &'static str {
+    match state {
+        AdditionalSignalState::Off => "Off",
+        AdditionalSignalState::Zs1 => "Zs1",
+        AdditionalSignalState::Zs6 => "Zs6",
+        AdditionalSignalState::Zs7 => "Zs7",
+        AdditionalSignalState::Zs8 => "Zs8",
+        AdditionalSignalState::Zs13 => "Zs13",
+    }
+}
+
+pub(crate) fn zs3_to_number(symbol: &AdditionalSignalZs3Symbol) -> u32 {
+    match symbol {
+        AdditionalSignalZs3Symbol::OFF => 0,
+        AdditionalSignalZs3Symbol::ONE => 1,
+        AdditionalSignalZs3Symbol::TWO => 2,
+        AdditionalSignalZs3Symbol::THREE => 3,
+        AdditionalSignalZs3Symbol::FOUR => 4,
+        AdditionalSignalZs3Symbol::FIVE => 5,
+        AdditionalSignalZs3Symbol::SIX => 6,
+        AdditionalSignalZs3Symbol::SEVEN => 7,
+        AdditionalSignalZs3Symbol::EIGHT => 8,
+        AdditionalSignalZs3Symbol::NINE => 9,
+        AdditionalSignalZs3Symbol::TEN => 10,
+        AdditionalSignalZs3Symbol::ELEVEN => 11,
+        AdditionalSignalZs3Symbol::TWELVE => 12,
+        AdditionalSignalZs3Symbol::THIRTEEN => 13,
+        AdditionalSignalZs3Symbol::FOURTEEN => 14,
+        AdditionalSignalZs3Symbol::FIFTEEN => 15,
+        AdditionalSignalZs3Symbol::SIXTEEN => 16,
+    }
+}
+
+fn vacancy_section_state_name(state: &VacancySectionState) -> &'static str {
+    match state {
+        VacancySectionState::Free => "Free",
+        VacancySectionState::Occupied => "Occupied",
+        VacancySectionState::Disturbed => "Disturbed",
+        VacancySectionState::CommunicationError => "CommunicationError",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::{Code, Request};
+
+    use crate::{
+        changes::{observed_states, ObservedState},
+        fixture::{move_point, station},
+        ixl::{interlocking_server::Interlocking, ElementStateRequest, ElementType, MpDirection},
+    };
+
+    fn request(element: &str, element_type: ElementType) -> Request<ElementStateRequest> {
+        let mut request = ElementStateRequest {
+            element: element.to_string(),
+            ..Default::default()
+        };
+        request.set_type(element_type);
+        Request::new(request)
+    }
+
+    #[tokio::test]
+    async fn point_states_are_spelled_the_same_everywhere() {
+        let state = station().interlocking_state();
+        move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap();
+
+        let point_state =
+            Interlocking::get_point_state(&state, request("W1", ElementType::Unspecified))
+                .await
+                .unwrap();
+        assert_eq!(point_state.get_ref().state, "Right");
+        let details =
+            Interlocking::get_element_state(&state, request("W1", ElementType::ElementPoint))
+                .await
+                .unwrap();
+        assert_eq!(details.get_ref().state, "Right");
+        assert_eq!(
+            observed_states(&state).elements["W1"],
+            ObservedState::Point("Right")
+        );
+    }
+
+    #[tokio::test]
+    async fn element_types_are_checked() {
+        let state = station().interlocking_state();
+
+        let details =
+            Interlocking::get_element_state(&state, request("A", ElementType::ElementSignal))
+                .await
+                .unwrap();
+        assert_eq!(details.get_ref().state, "Hp0");
+
+        let status =
+            Interlocking::get_element_state(&state, request("W1", ElementType::Unspecified))
+                .await
+                .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status =
+            Interlocking::get_element_state(&state, request("W1", ElementType::ElementSignal))
+                .await
+                .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let status = Interlocking::get_point_state(&state, request("A", ElementType::Unspecified))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        let status =
+            Interlocking::get_point_state(&state, request("W1", ElementType::ElementSignal))
+                .await
+                .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+}
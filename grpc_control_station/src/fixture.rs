@@ -0,0 +1,146 @@
+//! A small station for tests. The driveways A - C and B - C run over point W1 and vacancy
+//! section T1 and conflict, C - D runs through T2:
+//!
+//! ```text
+//! A ──┐
+//!     W1 (T1) ── C ── T2 ── D
+//! B ──┘
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use tonic::Request;
+use track_element::{
+    driveway::{Driveway, DrivewayManager, DrivewayState},
+    point::{Point, PointState},
+    signal::{MainSignalState, Signal, SignalState, SupportedSignalStates},
+    vacancy_section::{VacancySection, VacancySectionState},
+    TrackElement,
+};
+
+use crate::{
+    ixl::{interlocking_server::Interlocking, MpCommand, MpDirection, RlrCommand, RrCommand},
+    lookup::{find_driveway, find_vacancy_section},
+    ControlStation, InterlockingState,
+};
+
+pub(crate) fn station() -> ControlStation {
+    let signal = |id: &str| {
+        Signal::new_arc(
+            SignalState::default(),
+            SupportedSignalStates::default()
+                .main(&mut vec![MainSignalState::Hp0, MainSignalState::Ks1]),
+            id.to_string(),
+            None,
+        )
+    };
+    let (a, b, c, d) = (signal("A"), signal("B"), signal("C"), signal("D"));
+    let w1 = Point::new_arc(PointState::Left, "W1".to_string());
+    let t1 = VacancySection::new_arc("T1".to_string(), VacancySectionState::Free, vec![]);
+    let t2 = VacancySection::new_arc("T2".to_string(), VacancySectionState::Free, vec![]);
+
+    let driveway = |start: &Arc<RwLock<Signal>>,
+                    end: &Arc<RwLock<Signal>>,
+                    points: Vec<(Arc<RwLock<Point>>, PointState)>,
+                    vacancy_section: &Arc<RwLock<VacancySection>>| {
+        let target_state = DrivewayState::new(
+            points,
+            vec![(start.clone(), MainSignalState::Ks1.into())],
+            vec![(vacancy_section.clone(), VacancySectionState::Free)],
+        );
+        Arc::new(RwLock::new(Driveway::new(
+            vec![],
+            target_state,
+            start.clone(),
+            end.clone(),
+        )))
+    };
+    let a_c = driveway(&a, &c, vec![(w1.clone(), PointState::Left)], &t1);
+    let b_c = driveway(&b, &c, vec![(w1, PointState::Right)], &t1);
+    let c_d = driveway(&c, &d, vec![], &t2);
+    a_c.write()
+        .unwrap()
+        .set_conflicting_driveways(&mut vec![b_c.clone()]);
+    b_c.write()
+        .unwrap()
+        .set_conflicting_driveways(&mut vec![a_c.clone()]);
+
+    let mut driveway_manager = DrivewayManager::new(BTreeMap::new());
+    for driveway in [a_c, b_c, c_d] {
+        driveway_manager.add(driveway);
+    }
+
+    let id = |start: &str, end: &str| (start.to_string(), end.to_string());
+    ControlStation::new(driveway_manager, "{}", "{}")
+        .point_sections(BTreeMap::from([("W1".to_string(), vec!["T1".to_string()])]))
+        .conflicting_driveways(BTreeMap::from([
+            (id("A", "C"), vec![id("B", "C")]),
+            (id("B", "C"), vec![id("A", "C")]),
+        ]))
+}
+
+pub(crate) async fn request_route(
+    state: &InterlockingState,
+    start: &str,
+    ziel: &str,
+) -> Result<(), tonic::Status> {
+    let command = RrCommand {
+        start: start.to_string(),
+        ziel: ziel.to_string(),
+    };
+    Interlocking::request_route(state, Request::new(command))
+        .await
+        .map(|_| ())
+}
+
+/// Change the state of a vacancy section as if it was reported by the field
+pub(crate) fn set_vacancy_section(
+    state: &InterlockingState,
+    id: &str,
+    new_state: VacancySectionState,
+) {
+    let (vacancy_section, _) =
+        find_vacancy_section(&state.driveway_manager.read().unwrap(), id).unwrap();
+    vacancy_section
+        .write()
+        .unwrap()
+        .set_state(new_state)
+        .unwrap();
+}
+
+pub(crate) async fn move_point(
+    state: &InterlockingState,
+    point: &str,
+    direction: MpDirection,
+) -> Result<(), tonic::Status> {
+    let mut command = MpCommand {
+        point: point.to_string(),
+        ..Default::default()
+    };
+    command.set_direction(direction);
+    Interlocking::move_point(state, Request::new(command))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) async fn release_route(
+    state: &InterlockingState,
+    start: &str,
+) -> Result<(), tonic::Status> {
+    let command = RlrCommand {
+        start: start.to_string(),
+    };
+    Interlocking::release_route(state, Request::new(command))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) fn is_set(state: &InterlockingState, start: &str, end: &str) -> bool {
+    let driveway_manager = state.driveway_manager.read().unwrap();
+    let (_, driveway) = find_driveway(&driveway_manager, start, end).unwrap();
+    let is_set = driveway.read().unwrap().is_set();
+    is_set
+}
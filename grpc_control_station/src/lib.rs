@@ -1,3 +1,5 @@
+pub use aspects::DerivedAspect;
+use axle_counter::AxleCounterResets;
 use axum::{
     extract::{ws::WebSocket, State, WebSocketUpgrade},
     response::IntoResponse,
@@ -5,39 +7,262 @@ use axum::{
     Json,
 };
 use axum_extra::routing::SpaRouter;
+use changes::{observed_states, PendingCommand, PublishedStates, StateUpdates};
+use element_state::{element_state, element_type_name};
 use ixl::{
-    interlocking_server::InterlockingServer, ElementStateRequest, ElementStateResponse, MpCommand,
-    MtpCommand, Nothing, RacCommand, RlrCommand, RrCommand,
+    interlocking_server::InterlockingServer, AuxiliaryActions, ElementStateDetails,
+    ElementStateRequest, ElementStateResponse, ElementType, MpCommand, MpDirection, MtpCommand,
+    MtpDirection, Nothing, RacCommand, RlrCommand, RouteRefusalDetails, RrCommand,
+    TrailedPointReport, VacancySectionOccupancy, VacancySectionReport,
 };
+use lookup::{
+    driveway_contains, driveway_name, find_point, find_set_driveway, find_vacancy_section,
+    locking_driveway,
+};
+use refusal::RouteRefusal;
+use transitions::valid_transitions;
 
 use serde_json::{Map, Value};
 
+use futures::{stream, Stream, StreamExt};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     net::SocketAddr,
+    pin::Pin,
     sync::{Arc, RwLock},
     time::Duration,
 };
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tonic::{transport::NamedService, Request, Response, Status};
 use tower_http::cors::CorsLayer;
 use track_element::{
-    driveway::DrivewayManager,
+    driveway::{Driveway, DrivewayManager},
     point::PointState,
     signal::{MainSignalState, SignalState},
     vacancy_section::VacancySectionState,
     TrackElement,
 };
 
+mod aspects;
+mod axle_counter;
+mod changes;
+mod element_state;
+#[cfg(test)]
+mod fixture;
+mod lookup;
+mod refusal;
+mod stop_aspect;
+mod transitions;
+
 pub mod ixl {
     tonic::include_proto!("ixl");
 }
 
+/// How many batches of state changes a slow subscriber may fall behind
+const CHANGE_CHANNEL_CAPACITY: usize = 64;
+/// How often to look for state changes that were not caused by a command
+const SUPERVISION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The conflicting driveways of each driveway, all given by their start and end signal IDs
+pub type ConflictTable = BTreeMap<(String, String), Vec<(String, String)>>;
+
+#[derive(Debug, Error)]
+pub enum ControlStationError {
+    #[error("Unknown point {0}")]
+    UnknownPoint(String),
+    #[error("Unknown vacancy section {0}")]
+    UnknownVacancySection(String),
+    #[error("Could not change the state of {element}: {message}")]
+    InvalidState { element: String, message: String },
+}
+
+/// State shared between the gRPC service and the WebSocket handlers
 #[derive(Clone)]
-pub struct WsState {
+pub struct InterlockingState {
     driveway_manager: Arc<RwLock<DrivewayManager>>,
+    /// The vacancy sections each point may lie in
+    point_sections: Arc<BTreeMap<String, Vec<String>>>,
+    /// How long to wait before releasing a driveway whose approach section is not clear.
+    /// If this is `None`, such releases are refused.
+    release_delay: Option<Duration>,
+    /// How often each driveway was set or released. A delayed release only goes ahead if
+    /// this did not change while it was waiting.
+    driveway_generations: Arc<RwLock<BTreeMap<String, u64>>>,
+    axle_counter_resets: Arc<RwLock<AxleCounterResets>>,
+    /// Points that were run through against their position and are no longer supervised
+    trailed_points: Arc<RwLock<BTreeSet<String>>>,
+    /// The delayed command that is currently being executed, if any
+    pending_command: Arc<RwLock<Option<PendingCommand>>>,
+    /// Signals whose aspect follows the aspect of the next main signal
+    derived_aspects: Arc<Vec<DerivedAspect>>,
+    /// Used to name the set driveway that blocks a refused one
+    conflicting_driveways: Arc<ConflictTable>,
+    /// Element and driveway states that were last sent to subscribers
+    published: Arc<RwLock<PublishedStates>>,
+    changes: broadcast::Sender<StateUpdates>,
+}
+
+impl InterlockingState {
+    /// Compare the element states with the ones last sent and notify all subscribers about
+    /// the elements that changed. Every command handler calls this after changing states.
+    /// Each batch of changes gets the next sequence id.
+    fn notify_changes(&self) {
+        let states = observed_states(self);
+        let valid_transitions = valid_transitions(self);
+        let pending_command = self.pending_command.read().unwrap().clone();
+        let mut published = self.published.write().unwrap();
+
+        let updates = states.changes_since(&published.states);
+        if updates.is_empty()
+            && valid_transitions == published.valid_transitions
+            && pending_command == published.pending_command
+        {
+            return;
+        }
+
+        published.id += 1;
+        published.states = states;
+        published.valid_transitions = valid_transitions.clone();
+        published.pending_command = pending_command.clone();
+
+        // Sending only fails if there are no subscribers
+        let _ = self.changes.send(StateUpdates {
+            id: published.id,
+            updates,
+            valid_transitions,
+            pending_command,
+        });
+    }
+
+    /// The state of every element and driveway as last sent to subscribers
+    fn snapshot(&self) -> StateUpdates {
+        let published = self.published.read().unwrap();
+        StateUpdates {
+            id: published.id,
+            updates: published.states.updates(),
+            valid_transitions: published.valid_transitions.clone(),
+            pending_command: published.pending_command.clone(),
+        }
+    }
+
+    /// Report that a point was run through against its position. The point loses its
+    /// supervision and every set driveway over it is released. No driveway over it can be set
+    /// until the point is moved back with a `MoveTrailedPoint` command.
+    pub fn report_trailed_point(&self, point_id: &str) -> Result<(), ControlStationError> {
+        let driveways: Vec<_> = {
+            let driveway_manager = self.driveway_manager.read().unwrap();
+            if find_point(&driveway_manager, point_id).is_none() {
+                return Err(ControlStationError::UnknownPoint(point_id.to_string()));
+            }
+
+            println!("Point {point_id} was trailed");
+            self.trailed_points
+                .write()
+                .unwrap()
+                .insert(point_id.to_string());
+
+            driveway_manager
+                .get_driveway_ids()
+                .into_iter()
+                .filter_map(|id| {
+                    let driveway = driveway_manager.get(id)?;
+                    let blocked = {
+                        let driveway = driveway.read().unwrap();
+                        driveway.is_set() && driveway_contains(&driveway, point_id)
+                    };
+                    blocked.then(|| (id.to_string(), driveway))
+                })
+                .collect()
+        };
+
+        for (id, driveway) in driveways {
+            println!(
+                "Driveway {} is blocked by trailed point {point_id}",
+                driveway_name(&driveway.read().unwrap())
+            );
+            self.release_driveway(&id, &driveway, None);
+        }
+        self.notify_changes();
+        Ok(())
+    }
+
+    /// Put the start signal of a driveway back to stop and unlock its elements. With
+    /// `generation`, the driveway is only released if it was neither released nor set since.
+    fn release_driveway(
+        &self,
+        driveway_id: &str,
+        driveway: &Arc<RwLock<Driveway>>,
+        generation: Option<u64>,
+    ) {
+        let name = driveway_name(&driveway.read().unwrap());
+        {
+            let mut driveway_generations = self.driveway_generations.write().unwrap();
+            let current_generation = driveway_generations
+                .entry(driveway_id.to_string())
+                .or_default();
+            if matches!(generation, Some(generation) if generation != *current_generation) {
+                println!(
+                    "Driveway {name} was released or set again in the meantime, not releasing it"
+                );
+                drop(driveway_generations);
+                self.notify_changes();
+                return;
+            }
+
+            let mut driveway = driveway.write().unwrap();
+            if driveway.is_set() {
+                if let Err(e) = driveway
+                    .start_signal()
+                    .write()
+                    .unwrap()
+                    .set_state(MainSignalState::Hp0.into())
+                {
+                    println!("Error setting start signal of driveway {name} to stop: {e:?}");
+                }
+                driveway.unset_way();
+                *current_generation += 1;
+                println!("Released driveway {name}");
+            }
+        }
+        self.update_derived_aspects();
+        self.notify_changes();
+    }
+
+    /// Report a new state of a vacancy section, e.g. from the axle counters in the field.
+    /// A vacancy section in the preparatory state after an axle counter reset leaves it as
+    /// soon as a train has passed through, so every change must be reported here.
+    pub fn report_vacancy_section_state(
+        &self,
+        vacancy_section_id: &str,
+        new_state: VacancySectionState,
+    ) -> Result<(), ControlStationError> {
+        {
+            let driveway_manager = self.driveway_manager.read().unwrap();
+            let (vacancy_section, _) = find_vacancy_section(&driveway_manager, vacancy_section_id)
+                .ok_or_else(|| {
+                    ControlStationError::UnknownVacancySection(vacancy_section_id.to_string())
+                })?;
+            vacancy_section
+                .write()
+                .unwrap()
+                .set_state(new_state)
+                .map_err(|e| ControlStationError::InvalidState {
+                    element: vacancy_section_id.to_string(),
+                    message: format!("{e:?}"),
+                })?;
+            self.axle_counter_resets
+                .write()
+                .unwrap()
+                .update(vacancy_section_id, &new_state);
+        }
+        self.notify_changes();
+        Ok(())
+    }
 }
 
 pub struct ControlStation {
-    driveway_manager: Arc<RwLock<DrivewayManager>>,
+    state: InterlockingState,
 
     topology: String,
     placement: String,
@@ -58,17 +283,67 @@ impl ControlStation {
         }
 
         Self {
-            driveway_manager: Arc::new(RwLock::new(driveway_manager)),
+            state: InterlockingState {
+                driveway_manager: Arc::new(RwLock::new(driveway_manager)),
+                point_sections: Arc::new(BTreeMap::new()),
+                release_delay: None,
+                driveway_generations: Arc::new(RwLock::new(BTreeMap::new())),
+                axle_counter_resets: Arc::new(RwLock::new(AxleCounterResets::default())),
+                trailed_points: Arc::new(RwLock::new(BTreeSet::new())),
+                pending_command: Arc::new(RwLock::new(None)),
+                derived_aspects: Arc::new(vec![]),
+                conflicting_driveways: Arc::new(BTreeMap::new()),
+                published: Arc::new(RwLock::new(PublishedStates::default())),
+                changes: broadcast::channel(CHANGE_CHANNEL_CAPACITY).0,
+            },
             topology: topology.into(),
             placement: placement.into(),
         }
     }
 
+    /// Set the vacancy sections each point may lie in (point ID -> vacancy section IDs).
+    /// Points cannot be moved unless all of these vacancy sections are free. Points without
+    /// a vacancy section are not checked for occupancy.
+    pub fn point_sections(mut self, point_sections: BTreeMap<String, Vec<String>>) -> Self {
+        self.state.point_sections = Arc::new(point_sections);
+        self
+    }
+
+    /// Release driveways whose approach section is not clear only after the given delay
+    /// instead of refusing the release
+    pub fn release_delay(mut self, release_delay: Duration) -> Self {
+        self.state.release_delay = Some(release_delay);
+        self
+    }
+
+    /// Set the signals whose aspect is derived from the aspect of the end signal of a
+    /// driveway. They are updated whenever a driveway is set or released.
+    pub fn derived_aspects(mut self, derived_aspects: Vec<DerivedAspect>) -> Self {
+        self.state.derived_aspects = Arc::new(derived_aspects);
+        self
+    }
+
+    /// Set the conflicting driveways of each driveway. A refused driveway names the set
+    /// driveway from this table that blocks it.
+    /// Without it, the refusal can only name a set driveway that shares an element.
+    pub fn conflicting_driveways(mut self, conflicting_driveways: ConflictTable) -> Self {
+        self.state.conflicting_driveways = Arc::new(conflicting_driveways);
+        self
+    }
+
+    /// A handle to the state of the interlocking, e.g. to report trailed points
+    pub fn interlocking_state(&self) -> InterlockingState {
+        self.state.clone()
+    }
+
     pub async fn listen(&mut self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
-        let ixl_server = InterlockingServer::new(self.driveway_manager.clone());
+        self.state.notify_changes();
+        tokio::spawn(supervise(self.state.clone()));
+
+        let ixl_server = InterlockingServer::new(self.state.clone());
         let grpc_path = format!(
             "/{}/:cmd",
-            <InterlockingServer<Arc<RwLock<DrivewayManager>>> as NamedService>::NAME
+            <InterlockingServer<InterlockingState> as NamedService>::NAME
         );
 
         let grpc_service = tonic_web::enable(ixl_server);
@@ -82,16 +357,9 @@ impl ControlStation {
             .route("/topology", get(|| async { Json(topology) }))
             .route("/topology/placement", get(|| async { Json(placement) }))
             .route(&grpc_path, axum::routing::any_service(grpc_service))
-            .route(
-                "/terminate",
-                get(|| async {
-                    std::process::exit(0);
-                }),
-            )
+            .route("/terminate", get(terminate))
             .layer(CorsLayer::permissive())
-            .with_state(WsState {
-                driveway_manager: self.driveway_manager.clone(),
-            })
+            .with_state(self.state.clone())
             .merge(frontend_router);
 
         axum::Server::bind(&addr)
@@ -101,18 +369,25 @@ impl ControlStation {
     }
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<WsState>) -> impl IntoResponse {
+async fn terminate() {
+    std::process::exit(0);
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<InterlockingState>,
+) -> impl IntoResponse {
     ws.on_upgrade(move |socket| handle_socket(socket, state))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: WsState) {
-    loop {
-        let driveway_manager = state.driveway_manager.clone();
+async fn handle_socket(mut socket: WebSocket, state: InterlockingState) {
+    // Subscribe before taking the snapshot so no change gets lost in between
+    let mut changes = state.changes.subscribe();
+    let mut message = driveway_state_to_json(&state.snapshot());
 
+    loop {
         if socket
-            .send(axum::extract::ws::Message::Text(
-                driveway_state_to_json(driveway_manager).to_string(),
-            ))
+            .send(axum::extract::ws::Message::Text(message.to_string()))
             .await
             .is_err()
         {
@@ -120,13 +395,56 @@ async fn handle_socket(mut socket: WebSocket, state: WsState) {
             break;
         }
 
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        message = match changes.recv().await {
+            Ok(changes) => driveway_state_to_json(&changes),
+            // This client could not keep up, so it gets a full snapshot instead
+            Err(RecvError::Lagged(_)) => driveway_state_to_json(&state.snapshot()),
+            Err(RecvError::Closed) => break,
+        };
+    }
+}
+
+/// Pick up changes that do not go through a command or report, e.g. elements that were
+/// changed directly through the driveway manager
+async fn supervise(state: InterlockingState) {
+    loop {
+        tokio::time::sleep(SUPERVISION_INTERVAL).await;
+        state.notify_changes();
     }
 }
 
 #[tonic::async_trait]
-impl ixl::interlocking_server::Interlocking for Arc<RwLock<DrivewayManager>> {
+impl ixl::interlocking_server::Interlocking for InterlockingState {
     async fn move_point(&self, command: Request<MpCommand>) -> Result<Response<Nothing>, Status> {
+        let command = command.get_ref();
+        let target_state = match command.direction() {
+            MpDirection::MpLeft => PointState::Left,
+            MpDirection::MpRight => PointState::Right,
+        };
+        println!(
+            "Got request to move point {} to {}",
+            &command.point,
+            point_state_to_string(&target_state)
+        );
+
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        let (point, _) = find_point(&driveway_manager, &command.point)
+            .ok_or_else(|| Status::not_found(format!("Unknown point {}", &command.point)))?;
+
+        if let Some(reason) = self.point_blocked(&driveway_manager, &command.point) {
+            return Err(Status::failed_precondition(reason));
+        }
+
+        point
+            .write()
+            .unwrap()
+            .set_state(target_state)
+            .map_err(|e| {
+                Status::internal(format!("Could not move point {}: {e:?}", &command.point))
+            })?;
+
+        drop(driveway_manager);
+        self.notify_changes();
         Ok(Response::new(Nothing {}))
     }
 
@@ -134,6 +452,62 @@ impl ixl::interlocking_server::Interlocking for Arc<RwLock<DrivewayManager>> {
         &self,
         command: Request<MtpCommand>,
     ) -> Result<Response<Nothing>, Status> {
+        let command = command.get_ref();
+        let target_state = match command.direction() {
+            MtpDirection::Left => PointState::Left,
+            MtpDirection::Right => PointState::Right,
+        };
+        println!(
+            "Got request to move trailed point {} to {}",
+            &command.point,
+            point_state_to_string(&target_state)
+        );
+
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        let (point, _) = find_point(&driveway_manager, &command.point)
+            .ok_or_else(|| Status::not_found(format!("Unknown point {}", &command.point)))?;
+
+        let mut trailed_points = self.trailed_points.write().unwrap();
+        if !trailed_points.contains(&command.point) {
+            return Err(Status::failed_precondition(format!(
+                "Point {} was not trailed",
+                &command.point
+            )));
+        }
+
+        if let Some(driveway) = locking_driveway(&driveway_manager, &command.point) {
+            return Err(Status::failed_precondition(format!(
+                "Point {} is locked by driveway {driveway}",
+                &command.point
+            )));
+        }
+
+        point
+            .write()
+            .unwrap()
+            .set_state(target_state)
+            .map_err(|e| {
+                Status::internal(format!("Could not move point {}: {e:?}", &command.point))
+            })?;
+        trailed_points.remove(&command.point);
+
+        drop(trailed_points);
+        drop(driveway_manager);
+        self.notify_changes();
+        Ok(Response::new(Nothing {}))
+    }
+
+    async fn report_trailed_point(
+        &self,
+        report: Request<TrailedPointReport>,
+    ) -> Result<Response<Nothing>, Status> {
+        let point = &report.get_ref().point;
+        println!("Got report that point {point} was trailed");
+
+        InterlockingState::report_trailed_point(self, point).map_err(|e| match e {
+            ControlStationError::UnknownPoint(_) => Status::not_found(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        })?;
         Ok(Response::new(Nothing {}))
     }
 
@@ -141,40 +515,236 @@ impl ixl::interlocking_server::Interlocking for Arc<RwLock<DrivewayManager>> {
         &self,
         command: Request<RacCommand>,
     ) -> Result<Response<Nothing>, Status> {
+        let tps = &command.get_ref().tps;
+        println!("Got request to reset the axle counter of vacancy section {tps}");
+
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        let (vacancy_section, state) = find_vacancy_section(&driveway_manager, tps)
+            .ok_or_else(|| Status::not_found(format!("Unknown vacancy section {tps}")))?;
+
+        if let Some(driveway) = locking_driveway(&driveway_manager, tps) {
+            return Err(Status::failed_precondition(format!(
+                "Vacancy section {tps} cannot be reset, it is part of the set driveway {driveway}"
+            )));
+        }
+
+        let mut axle_counter_resets = self.axle_counter_resets.write().unwrap();
+        if axle_counter_resets.is_preparatory(tps) {
+            return Err(Status::failed_precondition(format!(
+                "Vacancy section {tps} was already reset, confirm that it is clear with ConfirmAxleCounterReset"
+            )));
+        } else if matches!(
+            state,
+            VacancySectionState::Disturbed | VacancySectionState::CommunicationError
+        ) {
+            vacancy_section
+                .write()
+                .unwrap()
+                .set_state(VacancySectionState::Free)
+                .map_err(|e| {
+                    Status::internal(format!("Could not reset vacancy section {tps}: {e:?}"))
+                })?;
+            axle_counter_resets.reset(tps);
+        } else {
+            return Err(Status::failed_precondition(format!(
+                "Vacancy section {tps} is not disturbed"
+            )));
+        }
+
+        drop(axle_counter_resets);
+        drop(driveway_manager);
+        self.notify_changes();
+        Ok(Response::new(Nothing {}))
+    }
+
+    async fn confirm_axle_counter_reset(
+        &self,
+        command: Request<RacCommand>,
+    ) -> Result<Response<Nothing>, Status> {
+        let tps = &command.get_ref().tps;
+        println!("Got request to confirm the axle counter reset of vacancy section {tps}");
+
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        let (_, state) = find_vacancy_section(&driveway_manager, tps)
+            .ok_or_else(|| Status::not_found(format!("Unknown vacancy section {tps}")))?;
+
+        let mut axle_counter_resets = self.axle_counter_resets.write().unwrap();
+        if !axle_counter_resets.is_preparatory(tps) {
+            return Err(Status::failed_precondition(format!(
+                "Vacancy section {tps} is not waiting for the confirmation of an axle counter reset"
+            )));
+        }
+        if !matches!(state, VacancySectionState::Free) {
+            return Err(Status::failed_precondition(format!(
+                "Vacancy section {tps} cannot be confirmed clear, it is not free"
+            )));
+        }
+        axle_counter_resets.confirm(tps);
+
+        drop(axle_counter_resets);
+        drop(driveway_manager);
+        self.notify_changes();
+        Ok(Response::new(Nothing {}))
+    }
+
+    async fn report_vacancy_section_state(
+        &self,
+        report: Request<VacancySectionReport>,
+    ) -> Result<Response<Nothing>, Status> {
+        let report = report.get_ref();
+        let new_state = match report.state() {
+            VacancySectionOccupancy::OccupancyUnspecified => {
+                return Err(Status::invalid_argument(format!(
+                    "No state given for vacancy section {}",
+                    &report.vacancy_section
+                )));
+            }
+            VacancySectionOccupancy::OccupancyFree => VacancySectionState::Free,
+            VacancySectionOccupancy::OccupancyOccupied => VacancySectionState::Occupied,
+            VacancySectionOccupancy::OccupancyDisturbed => VacancySectionState::Disturbed,
+            VacancySectionOccupancy::OccupancyCommunicationError => {
+                VacancySectionState::CommunicationError
+            }
+        };
+
+        InterlockingState::report_vacancy_section_state(self, &report.vacancy_section, new_state)
+            .map_err(|e| match e {
+            ControlStationError::UnknownVacancySection(_) => Status::not_found(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        })?;
         Ok(Response::new(Nothing {}))
     }
 
+    async fn get_auxiliary_actions(
+        &self,
+        _request: Request<Nothing>,
+    ) -> Result<Response<AuxiliaryActions>, Status> {
+        let axle_counter_resets = self.axle_counter_resets.read().unwrap();
+        Ok(Response::new(AuxiliaryActions {
+            actions: axle_counter_resets
+                .log()
+                .iter()
+                .map(|action| ixl::AuxiliaryAction {
+                    vacancy_section: action.vacancy_section.clone(),
+                    description: action.description.clone(),
+                })
+                .collect(),
+            axle_counter_resets: axle_counter_resets.total(),
+        }))
+    }
+
     async fn request_route(
         &self,
         command: Request<RrCommand>,
     ) -> Result<Response<Nothing>, Status> {
-        println!(
-            "Got request for driveway {} - {}",
-            &command.get_ref().start,
-            &command.get_ref().ziel
-        );
-
-        match self
-            .write()
-            .unwrap()
-            .set_driveway(&command.get_ref().start, &command.get_ref().ziel)
-        {
-            Ok(()) => Ok(Response::new(Nothing {})),
+        let RrCommand { start, ziel } = command.get_ref();
+        println!("Got request for driveway {start} - {ziel}");
+
+        let result = {
+            let mut driveway_generations = self.driveway_generations.write().unwrap();
+            // Check and set the driveway under the same lock, so nothing can block it in between
+            let driveway_manager = &mut *self.driveway_manager.write().unwrap();
+            let driveway_id = self
+                .settable_driveway(driveway_manager, start, ziel)
+                .map_err(|refusal| {
+                    println!("{refusal}");
+                    Status::from(refusal)
+                })?;
+            let result = driveway_manager.set_driveway(start, ziel);
+            if result.is_ok() {
+                *driveway_generations.entry(driveway_id).or_default() += 1;
+            }
+            result
+        };
+        match result {
+            Ok(()) => {
+                self.update_derived_aspects();
+                self.notify_changes();
+                Ok(Response::new(Nothing {}))
+            }
             Err(e) => {
                 println!("Error setting driveway: {e:?}");
-                Err(Status::invalid_argument(format!(
-                    "Invalid driveway {} - {}",
-                    &command.get_ref().start,
-                    &command.get_ref().ziel
-                )))
+                Err(RouteRefusal::Rejected {
+                    driveway: format!("{start} - {ziel}"),
+                    reason: format!("{e:?}"),
+                }
+                .into())
             }
         }
     }
 
+    async fn check_route(
+        &self,
+        command: Request<RrCommand>,
+    ) -> Result<Response<RouteRefusalDetails>, Status> {
+        let RrCommand { start, ziel } = command.get_ref();
+        let driveway_manager = self.driveway_manager.read().unwrap();
+        let details = match self.settable_driveway(&driveway_manager, start, ziel) {
+            Ok(_) => RouteRefusalDetails::default(),
+            Err(refusal) => refusal.details(),
+        };
+        Ok(Response::new(details))
+    }
+
     async fn release_route(
         &self,
         command: Request<RlrCommand>,
     ) -> Result<Response<Nothing>, Status> {
+        let start = &command.get_ref().start;
+        println!("Got request to release driveway starting at {start}");
+
+        let (driveway_id, driveway, blocked_section) = {
+            let driveway_manager = self.driveway_manager.read().unwrap();
+            let (driveway_id, driveway) =
+                find_set_driveway(&driveway_manager, start).ok_or_else(|| {
+                    Status::not_found(format!("No driveway starting at {start} is set"))
+                })?;
+            let blocked_section = self.blocked_approach_section(&driveway_manager, start);
+            (driveway_id, driveway, blocked_section)
+        };
+        let name = driveway_name(&driveway.read().unwrap());
+
+        match (blocked_section, self.release_delay) {
+            (None, _) => self.release_driveway(&driveway_id, &driveway, None),
+            (Some((vacancy_section, reason)), None) => {
+                return Err(Status::failed_precondition(format!(
+                    "Driveway {name} cannot be released, approach section {vacancy_section} is blocked: {reason}"
+                )));
+            }
+            (Some((vacancy_section, reason)), Some(delay)) => {
+                println!(
+                    "Approach section {vacancy_section} is blocked ({reason}), releasing driveway {name} in {}s",
+                    delay.as_secs()
+                );
+                let pending_command = PendingCommand::ReleaseRoute {
+                    driveway: driveway_id.clone(),
+                    start: start.clone(),
+                };
+                *self.pending_command.write().unwrap() = Some(pending_command.clone());
+                self.notify_changes();
+
+                let generation = self
+                    .driveway_generations
+                    .read()
+                    .unwrap()
+                    .get(&driveway_id)
+                    .copied()
+                    .unwrap_or_default();
+                let state = self.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    {
+                        // Another delayed release may have replaced this one in the meantime
+                        let mut current_command = state.pending_command.write().unwrap();
+                        if current_command.as_ref() == Some(&pending_command) {
+                            *current_command = None;
+                        }
+                    }
+                    state.release_driveway(&driveway_id, &driveway, Some(generation));
+                });
+            }
+        }
+
         Ok(Response::new(Nothing {}))
     }
 
@@ -182,11 +752,82 @@ impl ixl::interlocking_server::Interlocking for Arc<RwLock<DrivewayManager>> {
         &self,
         command: Request<ElementStateRequest>,
     ) -> Result<Response<ElementStateResponse>, Status> {
-        println!("{}", command.get_ref().element);
+        let request = command.get_ref();
+        let element = &request.element;
+        if !matches!(
+            request.r#type(),
+            ElementType::Unspecified | ElementType::ElementPoint
+        ) {
+            return Err(Status::invalid_argument(format!(
+                "GetPointState only returns the state of points, not of a {}",
+                element_type_name(request.r#type())
+            )));
+        }
+
+        let details = element_state(self, element)
+            .filter(|details| details.r#type() == ElementType::ElementPoint)
+            .ok_or_else(|| Status::not_found(format!("Unknown point {element}")))?;
         Ok(Response::new(ElementStateResponse {
-            state: "left".to_string(),
+            state: details.state,
         }))
     }
+
+    type SubscribeStatesStream =
+        Pin<Box<dyn Stream<Item = Result<ixl::StateChange, Status>> + Send + 'static>>;
+
+    async fn subscribe_states(
+        &self,
+        _request: Request<Nothing>,
+    ) -> Result<Response<Self::SubscribeStatesStream>, Status> {
+        // Subscribe before taking the snapshot so no change gets lost in between
+        let receiver = self.changes.subscribe();
+        let snapshot = stream::iter(self.snapshot().updates);
+
+        let changes = stream::unfold(
+            (receiver, self.clone()),
+            |(mut receiver, state)| async move {
+                let updates = match receiver.recv().await {
+                    Ok(changes) => changes.updates,
+                    // This subscriber could not keep up, so it gets a full snapshot instead
+                    Err(RecvError::Lagged(_)) => state.snapshot().updates,
+                    Err(RecvError::Closed) => return None,
+                };
+                Some((stream::iter(updates), (receiver, state)))
+            },
+        )
+        .flatten();
+
+        Ok(Response::new(Box::pin(
+            snapshot
+                .chain(changes)
+                .map(|update| ixl::StateChange::from(&update))
+                .map(Ok),
+        )))
+    }
+
+    async fn get_element_state(
+        &self,
+        command: Request<ElementStateRequest>,
+    ) -> Result<Response<ElementStateDetails>, Status> {
+        let request = command.get_ref();
+        let element = &request.element;
+        let element_type = request.r#type();
+        if element_type == ElementType::Unspecified {
+            return Err(Status::invalid_argument(format!(
+                "No element type given for element {element}"
+            )));
+        }
+
+        element_state(self, element)
+            .filter(|details| details.r#type() == element_type)
+            .map(Response::new)
+            .ok_or_else(|| {
+                Status::not_found(format!(
+                    "Unknown {} {element}",
+                    element_type_name(element_type)
+                ))
+            })
+    }
 }
 
 fn point_state_to_string(state: &PointState) -> &'static str {
@@ -227,43 +868,24 @@ fn vacancy_section_state_to_string(state: &VacancySectionState) -> &'static str
     }
 }
 
-fn driveway_state_to_json(driveway_manager: Arc<RwLock<DrivewayManager>>) -> Value {
-    let driveway_manager = driveway_manager.read().unwrap();
-    let state = driveway_manager.state();
-    let points = state.points();
-    let signals = state.signals();
-    let vacancy_sections = state.vacancy_sections();
-
+/// Serialize element states for the WebSocket feed. This is used both for full snapshots
+/// and for deltas that only contain the changed elements. The valid transitions are the
+/// ones that were computed together with the changes.
+fn driveway_state_to_json(changes: &StateUpdates) -> Value {
     let mut output = Value::Object(Map::new());
     output["states"] = Value::Object(Map::new());
 
-    for (point, state) in points {
-        output.get_mut("states").unwrap()[point.read().unwrap().id()] =
-            point_state_to_string(state).into();
-    }
-
-    for (signal, state) in signals {
-        output.get_mut("states").unwrap()[signal.read().unwrap().id()] =
-            signal_state_to_string(state).into();
-    }
-
-    for (vacancy_section, state) in vacancy_sections {
-        if matches!(state, VacancySectionState::Occupied) {
-            output.get_mut("states").unwrap()[vacancy_section.read().unwrap().id()] =
-                vacancy_section_state_to_string(&VacancySectionState::Occupied).into();
-        } else {
-            let state = if driveway_manager.is_allocated(&vacancy_section.read().unwrap()) {
-                "Allocated".into()
-            } else {
-                "Unallocated".into()
-            };
-            output.get_mut("states").unwrap()[vacancy_section.read().unwrap().id()] = state;
+    for update in &changes.updates {
+        if let Some(state) = update.state.ws_state() {
+            output.get_mut("states").unwrap()[&update.id] = state.into();
         }
     }
 
-    // TODO
-    output["pendingCommand"] = Value::Null;
-    output["id"] = Value::Null;
-    output["validTransitions"] = Value::Null;
+    output["pendingCommand"] = changes
+        .pending_command
+        .as_ref()
+        .map_or(Value::Null, PendingCommand::to_json);
+    output["id"] = changes.id.into();
+    output["validTransitions"] = changes.valid_transitions.clone();
     output
 }
@@ -0,0 +1,193 @@
+use std::sync::{Arc, RwLock};
+
+use track_element::{
+    driveway::{Driveway, DrivewayManager},
+    point::{Point, PointState},
+    signal::{Signal, SignalState},
+    vacancy_section::{VacancySection, VacancySectionState},
+    TrackElement,
+};
+
+/// Find a point and its current state by its ID
+pub(crate) fn find_point(
+    driveway_manager: &DrivewayManager,
+    id: &str,
+) -> Option<(Arc<RwLock<Point>>, PointState)> {
+    driveway_manager
+        .state()
+        .points()
+        .iter()
+        .find(|(point, _)| point.read().unwrap().id() == id)
+        .cloned()
+}
+
+/// Find a signal and its current state by its ID
+pub(crate) fn find_signal(
+    driveway_manager: &DrivewayManager,
+    id: &str,
+) -> Option<(Arc<RwLock<Signal>>, SignalState)> {
+    driveway_manager
+        .state()
+        .signals()
+        .iter()
+        .find(|(signal, _)| signal.read().unwrap().id() == id)
+        .cloned()
+}
+
+/// Find a vacancy section and its current state by its ID
+pub(crate) fn find_vacancy_section(
+    driveway_manager: &DrivewayManager,
+    id: &str,
+) -> Option<(Arc<RwLock<VacancySection>>, VacancySectionState)> {
+    driveway_manager
+        .state()
+        .vacancy_sections()
+        .iter()
+        .find(|(vacancy_section, _)| vacancy_section.read().unwrap().id() == id)
+        .cloned()
+}
+
+/// Check whether a track element is part of the target state of a driveway
+pub(crate) fn driveway_contains(driveway: &Driveway, element_id: &str) -> bool {
+    let target_state = driveway.target_state();
+    target_state
+        .points()
+        .iter()
+        .any(|(point, _)| point.read().unwrap().id() == element_id)
+        || target_state
+            .signals()
+            .iter()
+            .any(|(signal, _)| signal.read().unwrap().id() == element_id)
+        || target_state
+            .vacancy_sections()
+            .iter()
+            .any(|(vacancy_section, _)| vacancy_section.read().unwrap().id() == element_id)
+}
+
+/// How a driveway is shown to dispatchers: by the IDs of its start and end signal, e.g. `A - C`
+pub(crate) fn driveway_name(driveway: &Driveway) -> String {
+    format!(
+        "{} - {}",
+        driveway.start_signal().read().unwrap().id(),
+        driveway.end_signal().read().unwrap().id()
+    )
+}
+
+/// The name of the set driveway that locks a track element, if any
+pub(crate) fn locking_driveway(
+    driveway_manager: &DrivewayManager,
+    element_id: &str,
+) -> Option<String> {
+    driveway_manager
+        .get_driveway_ids()
+        .into_iter()
+        .find_map(|id| {
+            let driveway = driveway_manager.get(id)?;
+            let driveway = driveway.read().unwrap();
+            (driveway.is_set() && driveway_contains(&driveway, element_id))
+                .then(|| driveway_name(&driveway))
+        })
+}
+
+/// The name of a set driveway that locks one of the elements of the given driveway, if any
+pub(crate) fn set_driveway_sharing_elements(
+    driveway_manager: &DrivewayManager,
+    driveway: &Driveway,
+) -> Option<String> {
+    let target_state = driveway.target_state();
+    target_state
+        .points()
+        .iter()
+        .map(|(point, _)| point.read().unwrap().id().to_string())
+        .chain(
+            target_state
+                .signals()
+                .iter()
+                .map(|(signal, _)| signal.read().unwrap().id().to_string()),
+        )
+        .chain(
+            target_state
+                .vacancy_sections()
+                .iter()
+                .map(|(vacancy_section, _)| vacancy_section.read().unwrap().id().to_string()),
+        )
+        .find_map(|element_id| locking_driveway(driveway_manager, &element_id))
+}
+
+/// The name of the first set driveway among the given ones, which are given by their start
+/// and end signal IDs
+pub(crate) fn first_set_driveway(
+    driveway_manager: &DrivewayManager,
+    driveways: &[(String, String)],
+) -> Option<String> {
+    driveways.iter().find_map(|(start, end)| {
+        let (_, driveway) = find_driveway(driveway_manager, start, end)?;
+        let is_set = driveway.read().unwrap().is_set();
+        is_set.then(|| format!("{start} - {end}"))
+    })
+}
+
+/// Find the set driveway that starts at the given signal
+pub(crate) fn find_set_driveway(
+    driveway_manager: &DrivewayManager,
+    start_signal_id: &str,
+) -> Option<(String, Arc<RwLock<Driveway>>)> {
+    driveway_manager
+        .get_driveway_ids()
+        .into_iter()
+        .filter_map(|id| {
+            driveway_manager
+                .get(id)
+                .map(|driveway| (id.to_string(), driveway))
+        })
+        .find(|(_, driveway)| {
+            let driveway = driveway.read().unwrap();
+            driveway.is_set() && driveway.start_signal().read().unwrap().id() == start_signal_id
+        })
+}
+
+/// The vacancy sections in front of a signal, i.e. the vacancy sections of all driveways
+/// that end at that signal
+pub(crate) fn approach_sections(
+    driveway_manager: &DrivewayManager,
+    signal_id: &str,
+) -> Vec<String> {
+    let mut approach_sections = vec![];
+    for id in driveway_manager.get_driveway_ids() {
+        let Some(driveway) = driveway_manager.get(id) else {
+            continue;
+        };
+        let driveway = driveway.read().unwrap();
+        if driveway.end_signal().read().unwrap().id() != signal_id {
+            continue;
+        }
+        for (vacancy_section, _) in driveway.target_state().vacancy_sections() {
+            let vacancy_section_id = vacancy_section.read().unwrap().id().to_string();
+            if !approach_sections.contains(&vacancy_section_id) {
+                approach_sections.push(vacancy_section_id);
+            }
+        }
+    }
+    approach_sections
+}
+
+/// Find the driveway between two signals
+pub(crate) fn find_driveway(
+    driveway_manager: &DrivewayManager,
+    start_signal_id: &str,
+    end_signal_id: &str,
+) -> Option<(String, Arc<RwLock<Driveway>>)> {
+    driveway_manager
+        .get_driveway_ids()
+        .into_iter()
+        .filter_map(|id| {
+            driveway_manager
+                .get(id)
+                .map(|driveway| (id.to_string(), driveway))
+        })
+        .find(|(_, driveway)| {
+            let driveway = driveway.read().unwrap();
+            driveway.start_signal().read().unwrap().id() == start_signal_id
+                && driveway.end_signal().read().unwrap().id() == end_signal_id
+        })
+}
@@ -0,0 +1,157 @@
+use prost::{bytes::Bytes, Message};
+use thiserror::Error;
+use tonic::{Code, Status};
+
+use crate::ixl::{RouteRefusalCode, RouteRefusalDetails};
+
+/// Why a driveway cannot be set
+#[derive(Clone, Debug, Error, PartialEq)]
+pub(crate) enum RouteRefusal {
+    #[error("Unknown driveway {start} - {ziel}")]
+    UnknownDriveway { start: String, ziel: String },
+    #[error("Driveway {driveway} is already set")]
+    AlreadySet { driveway: String },
+    #[error(
+        "Driveway {driveway} conflicts with the set driveway {}",
+        .blocking_driveway.as_deref().unwrap_or("(unknown)")
+    )]
+    ConflictingDriveway {
+        driveway: String,
+        /// `None` if the conflicting driveway is neither in the conflict table nor shares an
+        /// element with this one
+        blocking_driveway: Option<String>,
+    },
+    #[error("Driveway {driveway} needs point {point} in another position, it is locked by driveway {blocking_driveway}")]
+    LockedPoint {
+        driveway: String,
+        point: String,
+        blocking_driveway: String,
+    },
+    #[error("Driveway {driveway} is blocked by trailed point {point}")]
+    TrailedPoint { driveway: String, point: String },
+    #[error("Driveway {driveway} is blocked by vacancy section {vacancy_section}, it is not free")]
+    OccupiedVacancySection {
+        driveway: String,
+        vacancy_section: String,
+    },
+    #[error("Driveway {driveway} is blocked by vacancy section {vacancy_section}, its axle counter reset is not confirmed yet")]
+    UnconfirmedAxleCounterReset {
+        driveway: String,
+        vacancy_section: String,
+    },
+    #[error("Driveway {driveway} was rejected by the interlocking: {reason}")]
+    Rejected { driveway: String, reason: String },
+}
+
+impl RouteRefusal {
+    pub(crate) fn code(&self) -> RouteRefusalCode {
+        match self {
+            RouteRefusal::UnknownDriveway { .. } => RouteRefusalCode::UnknownDriveway,
+            RouteRefusal::AlreadySet { .. } => RouteRefusalCode::DrivewayAlreadySet,
+            RouteRefusal::ConflictingDriveway { .. } => RouteRefusalCode::ConflictingDriveway,
+            RouteRefusal::LockedPoint { .. } => RouteRefusalCode::LockedPoint,
+            RouteRefusal::TrailedPoint { .. } => RouteRefusalCode::TrailedPoint,
+            RouteRefusal::OccupiedVacancySection { .. } => RouteRefusalCode::OccupiedVacancySection,
+            RouteRefusal::UnconfirmedAxleCounterReset { .. } => {
+                RouteRefusalCode::UnconfirmedAxleCounterReset
+            }
+            RouteRefusal::Rejected { .. } => RouteRefusalCode::RejectedByInterlocking,
+        }
+    }
+
+    pub(crate) fn details(&self) -> RouteRefusalDetails {
+        let mut details = RouteRefusalDetails {
+            message: self.to_string(),
+            ..Default::default()
+        };
+        details.set_code(self.code());
+
+        match self {
+            RouteRefusal::UnknownDriveway { .. } => {}
+            RouteRefusal::AlreadySet { driveway } | RouteRefusal::Rejected { driveway, .. } => {
+                details.driveway = driveway.clone();
+            }
+            RouteRefusal::ConflictingDriveway {
+                driveway,
+                blocking_driveway,
+            } => {
+                details.driveway = driveway.clone();
+                details.blocking_driveway = blocking_driveway.clone().unwrap_or_default();
+            }
+            RouteRefusal::LockedPoint {
+                driveway,
+                point,
+                blocking_driveway,
+            } => {
+                details.driveway = driveway.clone();
+                details.element = point.clone();
+                details.blocking_driveway = blocking_driveway.clone();
+            }
+            RouteRefusal::TrailedPoint { driveway, point } => {
+                details.driveway = driveway.clone();
+                details.element = point.clone();
+            }
+            RouteRefusal::OccupiedVacancySection {
+                driveway,
+                vacancy_section,
+            }
+            | RouteRefusal::UnconfirmedAxleCounterReset {
+                driveway,
+                vacancy_section,
+            } => {
+                details.driveway = driveway.clone();
+                details.element = vacancy_section.clone();
+            }
+        }
+        details
+    }
+}
+
+impl From<RouteRefusal> for Status {
+    /// The refusal as a gRPC status, with [`RouteRefusalDetails`] encoded in its details
+    fn from(refusal: RouteRefusal) -> Self {
+        let code = match refusal {
+            RouteRefusal::UnknownDriveway { .. } => Code::InvalidArgument,
+            _ => Code::FailedPrecondition,
+        };
+        Status::with_details(
+            code,
+            refusal.to_string(),
+            Bytes::from(refusal.details().encode_to_vec()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use tonic::Code;
+
+    use crate::{
+        fixture::{request_route, station},
+        ixl::{RouteRefusalCode, RouteRefusalDetails},
+    };
+
+    #[tokio::test]
+    async fn refusals_name_driveways_by_their_signals() {
+        let state = station().interlocking_state();
+        request_route(&state, "A", "C").await.unwrap();
+
+        let status = request_route(&state, "B", "C").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status.message(),
+            "Driveway B - C needs point W1 in another position, it is locked by driveway A - C"
+        );
+        let details = RouteRefusalDetails::decode(status.details()).unwrap();
+        assert_eq!(details.code(), RouteRefusalCode::LockedPoint);
+        assert_eq!(details.driveway, "B - C");
+        assert_eq!(details.blocking_driveway, "A - C");
+        assert_eq!(details.element, "W1");
+
+        let status = request_route(&state, "A", "D").await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let details = RouteRefusalDetails::decode(status.details()).unwrap();
+        assert_eq!(details.code(), RouteRefusalCode::UnknownDriveway);
+    }
+}
@@ -0,0 +1,12 @@
+//! Which aspects tell trains to stop. The code generator includes this file with `#[path]`, so
+//! it may only depend on `track_element`.
+
+use track_element::signal::MainSignalState::{self, *};
+
+/// Whether a main signal aspect tells trains to stop, or at least not to pass on a driveway
+pub fn is_stop(aspect: &MainSignalState) -> bool {
+    matches!(
+        aspect,
+        Hp0 | Hp0PlusSh1 | Hp0WithDrivingIndicator | Hp0Hv | Sh1 | IdLight | Off
+    )
+}
@@ -0,0 +1,367 @@
+use serde_json::{json, Value};
+use track_element::{
+    driveway::{Driveway, DrivewayManager},
+    point::PointState,
+    vacancy_section::VacancySectionState,
+    TrackElement,
+};
+
+use crate::{
+    lookup::{
+        approach_sections, driveway_contains, driveway_name, find_driveway, find_vacancy_section,
+        first_set_driveway, locking_driveway, set_driveway_sharing_elements,
+    },
+    point_state_to_string,
+    refusal::RouteRefusal,
+    InterlockingState,
+};
+
+impl InterlockingState {
+    /// Why a point cannot be moved right now, or `None` if it can
+    pub(crate) fn point_blocked(
+        &self,
+        driveway_manager: &DrivewayManager,
+        point_id: &str,
+    ) -> Option<String> {
+        if self.trailed_points.read().unwrap().contains(point_id) {
+            return Some(format!(
+                "Point {point_id} was trailed and can only be moved with MoveTrailedPoint"
+            ));
+        }
+
+        if let Some(driveway) = locking_driveway(driveway_manager, point_id) {
+            return Some(format!("Point {point_id} is locked by driveway {driveway}"));
+        }
+
+        for vacancy_section in self.point_sections.get(point_id).into_iter().flatten() {
+            if let Some(reason) = self.vacancy_section_blocked(driveway_manager, vacancy_section) {
+                return Some(format!(
+                    "Point {point_id} cannot be moved, vacancy section {vacancy_section} is blocked: {reason}"
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Why a vacancy section cannot be relied on to be clear, or `None` if it is free and
+    /// no axle counter reset of it is waiting for confirmation
+    pub(crate) fn vacancy_section_blocked(
+        &self,
+        driveway_manager: &DrivewayManager,
+        vacancy_section_id: &str,
+    ) -> Option<&'static str> {
+        if self
+            .axle_counter_resets
+            .read()
+            .unwrap()
+            .is_preparatory(vacancy_section_id)
+        {
+            return Some("its axle counter reset is not confirmed yet");
+        }
+        match find_vacancy_section(driveway_manager, vacancy_section_id) {
+            Some((_, VacancySectionState::Free)) => None,
+            _ => Some("it is not free"),
+        }
+    }
+
+    /// Why a driveway cannot be set right now, or `None` if it can
+    pub(crate) fn route_refusal(
+        &self,
+        driveway_manager: &DrivewayManager,
+        driveway: &Driveway,
+    ) -> Option<RouteRefusal> {
+        let id = driveway_name(driveway);
+        if driveway.is_set() {
+            return Some(RouteRefusal::AlreadySet { driveway: id });
+        }
+
+        if let Some(point) = self
+            .trailed_points
+            .read()
+            .unwrap()
+            .iter()
+            .find(|point| driveway_contains(driveway, point))
+        {
+            return Some(RouteRefusal::TrailedPoint {
+                driveway: id,
+                point: point.clone(),
+            });
+        }
+
+        for (point, target_state) in driveway.target_state().points() {
+            let point = point.read().unwrap();
+            let in_position = matches!(
+                (point.state(), target_state),
+                (PointState::Left, PointState::Left) | (PointState::Right, PointState::Right)
+            );
+            if in_position {
+                continue;
+            }
+            if let Some(blocking_driveway) = locking_driveway(driveway_manager, point.id()) {
+                return Some(RouteRefusal::LockedPoint {
+                    driveway: id,
+                    point: point.id().to_string(),
+                    blocking_driveway,
+                });
+            }
+        }
+
+        if driveway.has_conflicting_driveways() {
+            let signals = (
+                driveway.start_signal().read().unwrap().id().to_string(),
+                driveway.end_signal().read().unwrap().id().to_string(),
+            );
+            let blocking_driveway = self
+                .conflicting_driveways
+                .get(&signals)
+                .and_then(|conflicts| first_set_driveway(driveway_manager, conflicts))
+                .or_else(|| set_driveway_sharing_elements(driveway_manager, driveway));
+            return Some(RouteRefusal::ConflictingDriveway {
+                driveway: id,
+                blocking_driveway,
+            });
+        }
+
+        let axle_counter_resets = self.axle_counter_resets.read().unwrap();
+        for (vacancy_section, _) in driveway.target_state().vacancy_sections() {
+            let vacancy_section = vacancy_section.read().unwrap();
+            let vacancy_section_id = vacancy_section.id().to_string();
+            if axle_counter_resets.is_preparatory(&vacancy_section_id) {
+                return Some(RouteRefusal::UnconfirmedAxleCounterReset {
+                    driveway: id,
+                    vacancy_section: vacancy_section_id,
+                });
+            }
+            if !matches!(vacancy_section.state(), VacancySectionState::Free) {
+                return Some(RouteRefusal::OccupiedVacancySection {
+                    driveway: id,
+                    vacancy_section: vacancy_section_id,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Look up a driveway and check whether it can be set right now
+    pub(crate) fn settable_driveway(
+        &self,
+        driveway_manager: &DrivewayManager,
+        start: &str,
+        ziel: &str,
+    ) -> Result<String, RouteRefusal> {
+        let (driveway_id, driveway) =
+            find_driveway(driveway_manager, start, ziel).ok_or_else(|| {
+                RouteRefusal::UnknownDriveway {
+                    start: start.to_string(),
+                    ziel: ziel.to_string(),
+                }
+            })?;
+        let driveway = driveway.read().unwrap();
+        match self.route_refusal(driveway_manager, &driveway) {
+            Some(refusal) => Err(refusal),
+            None => Ok(driveway_id),
+        }
+    }
+
+    /// A vacancy section in front of a signal that is not clear, if any, together with the
+    /// reason, see `vacancy_section_blocked`
+    pub(crate) fn blocked_approach_section(
+        &self,
+        driveway_manager: &DrivewayManager,
+        signal_id: &str,
+    ) -> Option<(String, &'static str)> {
+        approach_sections(driveway_manager, signal_id)
+            .into_iter()
+            .find_map(|id| {
+                let reason = self.vacancy_section_blocked(driveway_manager, &id)?;
+                Some((id, reason))
+            })
+    }
+}
+
+/// The operations a dispatcher can currently carry out, so the UI can grey out the
+/// ones that would be refused
+pub(crate) fn valid_transitions(state: &InterlockingState) -> Value {
+    let driveway_manager = state.driveway_manager.read().unwrap();
+    let pending_command = state.pending_command.read().unwrap().clone();
+
+    let mut request_route = vec![];
+    let mut release_route = vec![];
+    for id in driveway_manager.get_driveway_ids() {
+        let Some(driveway) = driveway_manager.get(id) else {
+            continue;
+        };
+        let driveway = driveway.read().unwrap();
+        let start = driveway.start_signal().read().unwrap().id().to_string();
+        let ziel = driveway.end_signal().read().unwrap().id().to_string();
+
+        if !driveway.is_set() {
+            if state.route_refusal(&driveway_manager, &driveway).is_none() {
+                request_route.push(json!({ "start": start, "ziel": ziel }));
+            }
+            continue;
+        }
+
+        let release_pending = matches!(&pending_command, Some(command) if command.releases(id));
+        let releasable = state.release_delay.is_some()
+            || state
+                .blocked_approach_section(&driveway_manager, &start)
+                .is_none();
+        if !release_pending && releasable {
+            release_route.push(json!({ "start": start }));
+        }
+    }
+
+    let mut move_point = vec![];
+    for (point, point_state) in driveway_manager.state().points() {
+        let point_id = point.read().unwrap().id().to_string();
+        if state.point_blocked(&driveway_manager, &point_id).is_some() {
+            continue;
+        }
+        let direction = match point_state {
+            PointState::Left => PointState::Right,
+            PointState::Right => PointState::Left,
+        };
+        move_point.push(json!({
+            "point": point_id,
+            "direction": point_state_to_string(&direction),
+        }));
+    }
+
+    json!({
+        "requestRoute": request_route,
+        "releaseRoute": release_route,
+        "movePoint": move_point,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tonic::{Code, Request};
+    use track_element::{signal::MainSignalState, vacancy_section::VacancySectionState};
+
+    use crate::{
+        fixture::{is_set, move_point, release_route, request_route, set_vacancy_section, station},
+        ixl::{interlocking_server::Interlocking, MpDirection, MtpCommand, MtpDirection},
+        lookup::find_signal,
+    };
+
+    #[tokio::test]
+    async fn points_can_be_moved_while_their_vacancy_section_is_free() {
+        let state = station().interlocking_state();
+        move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap();
+        move_point(&state, "W1", MpDirection::MpLeft).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unknown_points_are_not_found() {
+        let state = station().interlocking_state();
+        let status = move_point(&state, "W9", MpDirection::MpRight)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn locked_points_cannot_be_moved() {
+        let state = station().interlocking_state();
+        request_route(&state, "A", "C").await.unwrap();
+
+        let status = move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(status.message().contains("locked"), "{}", status.message());
+    }
+
+    #[tokio::test]
+    async fn points_cannot_be_moved_unless_their_vacancy_section_is_free() {
+        for vacancy_section_state in [
+            VacancySectionState::Occupied,
+            VacancySectionState::Disturbed,
+            VacancySectionState::CommunicationError,
+        ] {
+            let state = station().interlocking_state();
+            set_vacancy_section(&state, "T1", vacancy_section_state);
+
+            let status = move_point(&state, "W1", MpDirection::MpRight)
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+            assert!(status.message().contains("T1"), "{}", status.message());
+        }
+    }
+
+    #[tokio::test]
+    async fn release_is_refused_unless_the_approach_section_is_free() {
+        let state = station().interlocking_state();
+        request_route(&state, "C", "D").await.unwrap();
+        set_vacancy_section(&state, "T1", VacancySectionState::Disturbed);
+
+        let status = release_route(&state, "C").await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert!(is_set(&state, "C", "D"));
+
+        set_vacancy_section(&state, "T1", VacancySectionState::Free);
+        release_route(&state, "C").await.unwrap();
+        assert!(!is_set(&state, "C", "D"));
+    }
+
+    #[tokio::test]
+    async fn delayed_release_is_skipped_if_the_driveway_was_set_again() {
+        let state = station()
+            .release_delay(Duration::from_millis(50))
+            .interlocking_state();
+        request_route(&state, "C", "D").await.unwrap();
+        set_vacancy_section(&state, "T1", VacancySectionState::Occupied);
+        release_route(&state, "C").await.unwrap();
+        assert!(is_set(&state, "C", "D"));
+
+        // The train has stopped in front of C, the driveway is released and set again
+        set_vacancy_section(&state, "T1", VacancySectionState::Free);
+        release_route(&state, "C").await.unwrap();
+        request_route(&state, "C", "D").await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(is_set(&state, "C", "D"));
+        assert!(state.pending_command.read().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn trailed_points_block_driveways_until_they_are_moved_back() {
+        let state = station().interlocking_state();
+        request_route(&state, "A", "C").await.unwrap();
+        state.report_trailed_point("W1").unwrap();
+        assert!(!is_set(&state, "A", "C"));
+        let (_, signal_state) = find_signal(&state.driveway_manager.read().unwrap(), "A").unwrap();
+        assert_eq!(signal_state.main(), MainSignalState::Hp0);
+
+        let status = move_point(&state, "W1", MpDirection::MpRight)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        for (start, ziel) in [("A", "C"), ("B", "C")] {
+            let status = request_route(&state, start, ziel).await.unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+            assert!(status.message().contains("trailed"), "{}", status.message());
+        }
+        request_route(&state, "C", "D").await.unwrap();
+
+        let mut command = MtpCommand {
+            point: "W1".to_string(),
+            ..Default::default()
+        };
+        command.set_direction(MtpDirection::Right);
+        Interlocking::move_trailed_point(&state, Request::new(command))
+            .await
+            .unwrap();
+        move_point(&state, "W1", MpDirection::MpLeft).await.unwrap();
+        request_route(&state, "A", "C").await.unwrap();
+    }
+}